@@ -0,0 +1,189 @@
+//! Compares the work-stealing `ThreadPool` with the original design, where every worker
+//! receives jobs from one `Arc<Mutex<Receiver<Job>>>`.
+//!
+//! The jobs are empty, so the numbers are dominated by per job overhead. `ThreadPool` pays for
+//! features the channel pool does not have: it reads the clock twice per job for `PoolStats`,
+//! holds a guard per job for `wait_idle`, and takes a mutex to queue a job from outside the
+//! pool and another to take it from a worker deque. On a single CPU it is 2-3 times slower
+//! than the channel pool in every scenario. Results on multi-core machines still have to be
+//! collected before the work-stealing design can be called faster.
+//!
+//! Run with `cargo bench --bench scheduler`.
+
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use web_server_rust_book::ThreadPool;
+
+const WORKERS: usize = 4;
+const ROUNDS: usize = 5;
+
+/// The pool from the book: a single channel receiver shared behind a mutex.
+mod channel_pool {
+    use std::sync::{Arc, mpsc, Mutex};
+    use std::thread;
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    pub struct ChannelPool {
+        workers: Vec<thread::JoinHandle<()>>,
+        sender: Option<mpsc::Sender<Job>>,
+    }
+
+    impl ChannelPool {
+        pub fn new(size: usize) -> ChannelPool {
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let receiver = Arc::new(Mutex::new(receiver));
+
+            let workers = (0..size)
+                .map(|_| {
+                    let receiver = Arc::clone(&receiver);
+                    thread::spawn(move || loop {
+                        let message = receiver.lock().unwrap().recv();
+                        match message {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                })
+                .collect();
+
+            ChannelPool { workers, sender: Some(sender) }
+        }
+
+        pub fn execute<F>(&self, f: F)
+            where F: FnOnce() + Send + 'static,
+        {
+            self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+        }
+    }
+
+    impl Drop for ChannelPool {
+        fn drop(&mut self) {
+            drop(self.sender.take());
+            // The last owner may be one of the workers, which cannot join itself.
+            for worker in self.workers.drain(..) {
+                if worker.thread().id() != thread::current().id() {
+                    worker.join().unwrap();
+                }
+            }
+        }
+    }
+}
+
+use channel_pool::ChannelPool;
+
+/// Minimal interface shared by both pools so the scenarios can run against either.
+trait Pool: Send + Sync + 'static {
+    fn create(size: usize) -> Self;
+    fn run(&self, f: Box<dyn FnOnce() + Send + 'static>);
+}
+
+impl Pool for ThreadPool {
+    fn create(size: usize) -> Self {
+        ThreadPool::build(size).unwrap()
+    }
+
+    fn run(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(f).unwrap();
+    }
+}
+
+impl Pool for ChannelPool {
+    fn create(size: usize) -> Self {
+        ChannelPool::new(size)
+    }
+
+    fn run(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        self.execute(f);
+    }
+}
+
+/// Many empty jobs submitted by a single producer, like the accept loop in `main`.
+fn single_producer<P: Pool>(jobs: usize) -> Duration {
+    let pool = P::create(WORKERS);
+    let start = Instant::now();
+
+    for _ in 0..jobs {
+        pool.run(Box::new(|| {}));
+    }
+    drop(pool);
+
+    start.elapsed()
+}
+
+/// Empty jobs submitted concurrently by several producer threads.
+fn many_producers<P: Pool>(jobs: usize) -> Duration {
+    let pool = Arc::new(P::create(WORKERS));
+    let start = Instant::now();
+
+    let producers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                for _ in 0..jobs / WORKERS {
+                    pool.run(Box::new(|| {}));
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    drop(Arc::try_unwrap(pool).ok().unwrap());
+
+    start.elapsed()
+}
+
+/// Jobs that fan out into short child jobs submitted from the workers themselves.
+fn nested<P: Pool>(jobs: usize) -> Duration {
+    const CHILDREN: usize = 100;
+
+    let pool = Arc::new(P::create(WORKERS));
+    let (done_sender, done) = mpsc::channel();
+    let start = Instant::now();
+
+    for _ in 0..jobs / CHILDREN {
+        let inner = Arc::clone(&pool);
+        let done_sender = done_sender.clone();
+        pool.run(Box::new(move || {
+            for _ in 0..CHILDREN {
+                let done_sender = done_sender.clone();
+                inner.run(Box::new(move || done_sender.send(()).unwrap()));
+            }
+        }));
+    }
+    for _ in 0..jobs / CHILDREN * CHILDREN {
+        done.recv().unwrap();
+    }
+    let elapsed = start.elapsed();
+    drop(pool);
+
+    elapsed
+}
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn bench(name: &str, jobs: usize, scenario: fn(usize) -> Duration) {
+    let elapsed = median((0..ROUNDS).map(|_| scenario(jobs)).collect());
+    let rate = jobs as f64 / elapsed.as_secs_f64();
+
+    println!("{name:<40} {:>10.2} ms {:>14.0} jobs/s", elapsed.as_secs_f64() * 1000.0, rate);
+}
+
+fn main() {
+    const JOBS: usize = 200_000;
+
+    println!("{WORKERS} workers, {JOBS} jobs, median of {ROUNDS} rounds");
+
+    bench("single producer / channel pool", JOBS, single_producer::<ChannelPool>);
+    bench("single producer / work-stealing pool", JOBS, single_producer::<ThreadPool>);
+    bench("many producers / channel pool", JOBS, many_producers::<ChannelPool>);
+    bench("many producers / work-stealing pool", JOBS, many_producers::<ThreadPool>);
+    bench("nested / channel pool", JOBS, nested::<ChannelPool>);
+    bench("nested / work-stealing pool", JOBS, nested::<ThreadPool>);
+}
@@ -0,0 +1,93 @@
+/// Which CPU cores the workers of a pool are pinned to. Only applied on Linux.
+///
+/// Cores the process is not allowed to run on are ignored. A worker that cannot be pinned,
+/// because none of its cores are available or the platform has no affinity support, runs
+/// unpinned; `WorkerStats::cpus` reports the affinity each worker actually got.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Affinity {
+    /// Leave scheduling to the operating system.
+    #[default]
+    None,
+    /// Pin each worker to one of the available cores, round robin by worker id.
+    OnePerCore,
+    /// Pin each worker to one core of the list, round robin by worker id.
+    Cores(Vec<usize>),
+    /// Let every worker run on any core of the set.
+    CoreSet(Vec<usize>),
+}
+
+impl Affinity {
+    /// Returns the cores worker `id` should be pinned to out of `available`,
+    /// or `None` if it should not be pinned.
+    pub(crate) fn cores_for(&self, id: usize, available: &[usize]) -> Option<Vec<usize>> {
+        let cores: Vec<usize> = match self {
+            Affinity::None => return None,
+            Affinity::OnePerCore => available.to_vec(),
+            Affinity::Cores(cores) | Affinity::CoreSet(cores) => {
+                cores.iter().copied().filter(|core| available.contains(core)).collect()
+            }
+        };
+        if cores.is_empty() {
+            return None;
+        }
+
+        match self {
+            Affinity::CoreSet(_) => Some(cores),
+            _ => Some(vec![cores[id % cores.len()]]),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+
+    /// Number of bits in the kernel's default `cpu_set_t`.
+    const CPU_SETSIZE: usize = 1024;
+    const WORD_BITS: usize = u64::BITS as usize;
+
+    type CpuSet = [u64; CPU_SETSIZE / WORD_BITS];
+
+    extern "C" {
+        fn sched_getaffinity(pid: i32, cpusetsize: usize, mask: *mut CpuSet) -> i32;
+        fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const CpuSet) -> i32;
+    }
+
+    /// Cores the calling thread is allowed to run on.
+    pub(crate) fn available() -> io::Result<Vec<usize>> {
+        let mut set: CpuSet = [0; CPU_SETSIZE / WORD_BITS];
+        // SAFETY: `set` is a valid, writable buffer of the size passed; pid 0 is the calling thread.
+        if unsafe { sched_getaffinity(0, size_of::<CpuSet>(), &mut set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..CPU_SETSIZE).filter(|cpu| set[cpu / WORD_BITS] & (1 << (cpu % WORD_BITS)) != 0).collect())
+    }
+
+    /// Restricts the calling thread to `cores`.
+    pub(crate) fn pin(cores: &[usize]) -> io::Result<()> {
+        let mut set: CpuSet = [0; CPU_SETSIZE / WORD_BITS];
+        for &core in cores.iter().filter(|core| **core < CPU_SETSIZE) {
+            set[core / WORD_BITS] |= 1 << (core % WORD_BITS);
+        }
+        // SAFETY: `set` is a valid buffer of the size passed; pid 0 is the calling thread.
+        if unsafe { sched_setaffinity(0, size_of::<CpuSet>(), &set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    pub(crate) fn available() -> io::Result<Vec<usize>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "CPU affinity is only supported on Linux"))
+    }
+
+    pub(crate) fn pin(_cores: &[usize]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "CPU affinity is only supported on Linux"))
+    }
+}
+
+pub(crate) use sys::{available, pin};
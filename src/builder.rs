@@ -0,0 +1,260 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::affinity::Affinity;
+use crate::log::Logger;
+use crate::rejection::RejectionPolicy;
+use crate::watchdog::TimeLimits;
+use crate::{PoolError, PoolHandle, Shared, StatePool, supervisor, ThreadPool};
+
+/// Called on a worker thread with the worker id.
+pub(crate) type Hook = Arc<dyn Fn(usize) + Send + Sync + 'static>;
+
+/// Configures and creates a `ThreadPool`.
+///
+/// ```
+/// use web_server_rust_book::{RejectionPolicy, ThreadPoolBuilder};
+///
+/// let pool = ThreadPoolBuilder::new()
+///     .size(4)
+///     .queue_capacity(64)
+///     .rejection_policy(RejectionPolicy::CallerRuns)
+///     .thread_name_prefix("http")
+///     .stack_size(256 * 1024)
+///     .on_thread_start(|id| println!("http-{id} started"))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    pub(crate) size: usize,
+    pub(crate) max_size: Option<usize>,
+    pub(crate) keep_alive: Duration,
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) fair_capacity: Option<usize>,
+    pub(crate) rejection_policy: RejectionPolicy,
+    pub(crate) thread_name_prefix: String,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) on_thread_start: Option<Hook>,
+    pub(crate) on_thread_stop: Option<Hook>,
+    pub(crate) logger: Option<Arc<dyn Logger>>,
+    pub(crate) time_limits: TimeLimits,
+    pub(crate) affinity: Affinity,
+}
+
+impl Debug for ThreadPoolBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadPoolBuilder")
+            .field("size", &self.size)
+            .field("max_size", &self.max_size)
+            .field("keep_alive", &self.keep_alive)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("fair_capacity", &self.fair_capacity)
+            .field("rejection_policy", &self.rejection_policy)
+            .field("thread_name_prefix", &self.thread_name_prefix)
+            .field("stack_size", &self.stack_size)
+            .field("on_thread_start", &self.on_thread_start.is_some())
+            .field("on_thread_stop", &self.on_thread_stop.is_some())
+            .field("logger", &self.logger.is_some())
+            .field("time_limits", &self.time_limits)
+            .field("affinity", &self.affinity)
+            .finish()
+    }
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// Create a builder for a pool with 1 thread and an unbounded queue.
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size: 1,
+            max_size: None,
+            keep_alive: Duration::from_secs(60),
+            queue_capacity: None,
+            fair_capacity: None,
+            rejection_policy: RejectionPolicy::Block,
+            thread_name_prefix: String::from("worker"),
+            stack_size: None,
+            on_thread_start: None,
+            on_thread_stop: None,
+            logger: None,
+            time_limits: TimeLimits::default(),
+            affinity: Affinity::None,
+        }
+    }
+
+    /// Sets the number of threads in the pool.
+    ///
+    /// For an elastic pool this is the minimum number of threads.
+    pub fn size(mut self, size: usize) -> ThreadPoolBuilder {
+        self.size = size;
+        self
+    }
+
+    /// Makes the pool elastic, growing up to `max_size` threads when jobs back up in the queue.
+    ///
+    /// Threads above `size` retire after being idle for the keep-alive duration.
+    pub fn max_size(mut self, max_size: usize) -> ThreadPoolBuilder {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets how long an idle thread above `size` waits for a job before retiring.
+    /// Defaults to 60 seconds.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Limits the number of jobs waiting in the queue.
+    ///
+    /// When the queue is full `execute` applies the rejection policy, `try_execute` fails
+    /// immediately and `execute_timeout` waits up to its timeout.
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Limits the number of jobs of each key waiting in the fair queue of `execute_fair`.
+    ///
+    /// Fair jobs also count against the queue capacity; `execute_fair` rejects a job that
+    /// exceeds either limit.
+    pub fn max_queued_per_key(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.fair_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets what `execute` does when the queue is full. Defaults to `RejectionPolicy::Block`.
+    pub fn rejection_policy(mut self, policy: RejectionPolicy) -> ThreadPoolBuilder {
+        self.rejection_policy = policy;
+        self
+    }
+
+    /// Sets the prefix of the thread names. Workers are named `{prefix}-{id}`, the supervisor
+    /// and timer threads `{prefix}-supervisor` and `{prefix}-timer`. Defaults to `worker`.
+    pub fn thread_name_prefix(mut self, prefix: impl Into<String>) -> ThreadPoolBuilder {
+        self.thread_name_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the stack size of the worker threads in bytes. Defaults to the standard library's
+    /// default for spawned threads.
+    pub fn stack_size(mut self, stack_size: usize) -> ThreadPoolBuilder {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Sets a function run on every worker thread, including respawned ones, before it takes
+    /// its first job. It is given the worker id.
+    ///
+    /// A panic in the hook ends the worker thread for good: `build` returns
+    /// `PoolError::ThreadStart`, and a worker started later is not respawned.
+    pub fn on_thread_start<F>(mut self, f: F) -> ThreadPoolBuilder
+        where F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_thread_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets a function run on every worker thread just before it exits, also when it is
+    /// unwinding. It is given the worker id. A panic in the hook is ignored.
+    pub fn on_thread_stop<F>(mut self, f: F) -> ThreadPoolBuilder
+        where F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_thread_stop = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets where the pool sends its diagnostics, such as worker restarts and job panics.
+    /// By default the pool logs nothing.
+    pub fn logger(mut self, logger: impl Logger + 'static) -> ThreadPoolBuilder {
+        self.logger = Some(Arc::new(logger));
+        self
+    }
+
+    /// Reports jobs that run longer than `limit` to the logger and in `PoolStats::slow_jobs`.
+    pub fn soft_time_limit(mut self, limit: Duration) -> ThreadPoolBuilder {
+        self.time_limits.soft = Some(limit);
+        self
+    }
+
+    /// Replaces a worker whose job runs longer than `limit`, so a hung job does not reduce
+    /// the pool's capacity.
+    ///
+    /// Threads cannot be killed: the lost worker is detached and exits once its job returns.
+    /// It is no longer counted in `size` or waited for at shutdown.
+    pub fn hard_time_limit(mut self, limit: Duration) -> ThreadPoolBuilder {
+        self.time_limits.hard = Some(limit);
+        self
+    }
+
+    /// Sets which CPU cores the worker threads are pinned to. Defaults to `Affinity::None`.
+    pub fn affinity(mut self, affinity: Affinity) -> ThreadPoolBuilder {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Create a ThreadPool whose jobs receive a per-thread state built by `init`.
+    ///
+    /// # Result<StatePool<S>, PoolError>
+    ///
+    /// The `build_with_state` function fails like `build`.
+    pub fn build_with_state<S, I>(self, init: I) -> Result<StatePool<S>, PoolError>
+        where S: 'static,
+              I: Fn() -> S + Send + Sync + 'static,
+    {
+        Ok(StatePool::new(self.build()?, init))
+    }
+
+    /// Create the ThreadPool.
+    ///
+    /// # Result<ThreadPool, PoolError>
+    ///
+    /// The `build` function will return `PoolError::ZeroSize`, `PoolError::ZeroQueueCapacity` or
+    /// `PoolError::ZeroTimeLimit` if the size, a queue capacity or a time limit is 0, and `PoolError::MaxSizeBelowSize` if the maximum size
+    /// is smaller than the size.
+    /// The function will return `PoolError::Spawn` if a thread couldn't be created, and
+    /// `PoolError::ThreadStart` if the `on_thread_start` hook panicked on a worker.
+    pub fn build(self) -> Result<ThreadPool, PoolError> {
+        if self.size == 0 {
+            return Err(PoolError::ZeroSize);
+        }
+        if let Some(max_size) = self.max_size.filter(|max_size| *max_size < self.size) {
+            return Err(PoolError::MaxSizeBelowSize { size: self.size, max_size });
+        }
+        if self.queue_capacity == Some(0) || self.fair_capacity == Some(0) {
+            return Err(PoolError::ZeroQueueCapacity);
+        }
+        if [self.time_limits.soft, self.time_limits.hard].contains(&Some(Duration::ZERO)) {
+            return Err(PoolError::ZeroTimeLimit);
+        }
+        let (events, events_receiver) = mpsc::channel();
+
+        let shared = Arc::new(Shared::new(&self, events));
+
+        let mut pool = ThreadPool { handle: PoolHandle { shared }, supervisor: None };
+
+        pool.supervisor = Some(supervisor::spawn(Arc::clone(&pool.shared), events_receiver)?);
+
+        for _ in 0..self.size {
+            pool.shared.spawn_worker(&mut pool.shared.lock_workers())?;
+        }
+        let started: Vec<_> = pool.shared.lock_workers().iter_mut()
+            .filter_map(|worker| worker.started.take())
+            .collect();
+        for started in started {
+            if let Ok(Err(message)) = started.recv() {
+                return Err(PoolError::ThreadStart(message));
+            }
+        }
+
+        Ok(pool)
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Number of linked tokens after which dropped ones are pruned before adding another.
+const PRUNE_THRESHOLD: usize = 64;
+
+/// Flag that jobs check to stop early.
+///
+/// Cancellation is cooperative: a running job only stops if it checks `is_cancelled` or waits
+/// with `wait_timeout`. Cancelling a token also cancels every token created from it with
+/// `child_token`, so a group of jobs can share a parent. Clones share the same flag.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+#[derive(Default)]
+struct Node {
+    cancelled: AtomicBool,
+    /// Tokens cancelled together with this one. Guarded waits use the same lock.
+    children: Mutex<Vec<Weak<Node>>>,
+    changed: Condvar,
+}
+
+impl Node {
+    fn lock(&self) -> MutexGuard<'_, Vec<Weak<Node>>> {
+        self.children.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let children = std::mem::take(&mut *self.lock());
+        self.changed.notify_all();
+
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels this token and all of its children. Cancelling twice has no effect.
+    pub fn cancel(&self) {
+        self.node.cancel();
+    }
+
+    /// Returns `true` once the token or one of its parents has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Creates a token that is cancelled when this one is, but can also be cancelled on its own.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        self.link(&child);
+        child
+    }
+
+    /// Blocks until the token is cancelled or `timeout` elapses, whichever comes first.
+    ///
+    /// Returns `true` if the token was cancelled. Use it instead of `thread::sleep` in jobs
+    /// that should stop promptly.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut children = self.node.lock();
+        loop {
+            if self.is_cancelled() {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            children = self.node.changed.wait_timeout(children, deadline - now)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+
+    /// Makes `child` cancelled whenever this token is. A token may have several parents.
+    pub(crate) fn link(&self, child: &CancellationToken) {
+        let mut children = self.node.lock();
+        if self.is_cancelled() {
+            drop(children);
+            child.cancel();
+            return;
+        }
+
+        if children.len() >= PRUNE_THRESHOLD && children.len() == children.capacity() {
+            children.retain(|child| child.strong_count() > 0);
+        }
+        children.push(Arc::downgrade(&child.node));
+    }
+}
+
+impl Debug for CancellationToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,79 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::queue::TryExecuteError;
+
+/// Error returned by the fallible `ThreadPool` and `ThreadPoolBuilder` functions.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PoolError {
+    /// The pool size is 0.
+    ZeroSize,
+    /// The maximum size of an elastic pool is smaller than its size.
+    MaxSizeBelowSize { size: usize, max_size: usize },
+    /// The queue capacity is 0.
+    ZeroQueueCapacity,
+    /// The period of a repeating job is zero.
+    ZeroPeriod,
+    /// A soft or hard time limit is zero.
+    ZeroTimeLimit,
+    /// The weight of a fair queuing key is zero.
+    ZeroWeight,
+    /// A pool thread could not be started.
+    Spawn(io::Error),
+    /// The `on_thread_start` hook panicked with the given message.
+    ThreadStart(String),
+    /// The pool has been shut down and accepts no more jobs.
+    ShutDown,
+    /// The queue is at capacity and the job was rejected.
+    QueueFull,
+    /// The queue stayed at capacity until the timeout elapsed.
+    Timeout,
+}
+
+impl Display for PoolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PoolError::ZeroSize => f.write_str("pool size has to be greater than 0"),
+            PoolError::MaxSizeBelowSize { size, max_size } => {
+                write!(f, "maximum pool size {max_size} has to be at least the pool size {size}")
+            }
+            PoolError::ZeroQueueCapacity => f.write_str("queue capacity has to be greater than 0"),
+            PoolError::ZeroPeriod => f.write_str("period has to be greater than 0"),
+            PoolError::ZeroTimeLimit => f.write_str("time limit has to be greater than 0"),
+            PoolError::ZeroWeight => f.write_str("weight has to be greater than 0"),
+            PoolError::Spawn(_) => f.write_str("cannot spawn pool thread"),
+            PoolError::ThreadStart(message) => write!(f, "worker start hook panicked: {message}"),
+            PoolError::ShutDown => f.write_str("pool has been shut down"),
+            PoolError::QueueFull => f.write_str("job queue is full"),
+            PoolError::Timeout => f.write_str("timed out waiting for space in the job queue"),
+        }
+    }
+}
+
+impl Error for PoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolError::Spawn(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PoolError {
+    fn from(err: io::Error) -> Self {
+        PoolError::Spawn(err)
+    }
+}
+
+/// Drops the rejected job, keeping only the cause.
+impl<F> From<TryExecuteError<F>> for PoolError {
+    fn from(err: TryExecuteError<F>) -> Self {
+        match err {
+            TryExecuteError::Full(_) => PoolError::QueueFull,
+            TryExecuteError::Timeout(_) => PoolError::Timeout,
+            TryExecuteError::ShutDown(_) => PoolError::ShutDown,
+        }
+    }
+}
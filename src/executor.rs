@@ -0,0 +1,189 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use crate::handle::{self, Completer};
+use crate::{JobHandle, Priority, Shared};
+
+/// No poll is queued or running; a wakeup queues one.
+const IDLE: u8 = 0;
+/// A poll is queued.
+const SCHEDULED: u8 = 1;
+/// A worker is polling the future.
+const RUNNING: u8 = 2;
+/// Woken while being polled; polled again once the current poll returns.
+const NOTIFIED: u8 = 3;
+/// The future has completed, panicked or been dropped.
+const DONE: u8 = 4;
+
+/// A future spawned on a pool with `PoolHandle::spawn_future`.
+///
+/// The task is its own waker. Waking it queues a job that polls the future once, so a
+/// pending future occupies no worker, and at most one poll is queued or running at a time.
+pub(crate) struct FutureTask<T> {
+    future: Mutex<Option<Pin<Box<dyn Future<Output = T> + Send>>>>,
+    completer: Mutex<Option<Completer<T>>>,
+    state: AtomicU8,
+    shared: Weak<Shared>,
+}
+
+impl<T: Send + 'static> FutureTask<T> {
+    /// Creates a task for `future`, scheduled for its first poll, and the handle to its output.
+    pub(crate) fn new<F>(future: F, shared: &Arc<Shared>) -> (Arc<FutureTask<T>>, JobHandle<T>)
+        where F: Future<Output = T> + Send + 'static,
+    {
+        let (completer, handle) = handle::pair();
+        let task = FutureTask {
+            future: Mutex::new(Some(Box::pin(future))),
+            completer: Mutex::new(Some(completer)),
+            state: AtomicU8::new(SCHEDULED),
+            shared: Arc::downgrade(shared),
+        };
+        (Arc::new(task), handle)
+    }
+
+    fn future(&self) -> MutexGuard<'_, Option<Pin<Box<dyn Future<Output = T> + Send>>>> {
+        self.future.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn completer(&self) -> Option<Completer<T>> {
+        self.completer.lock().unwrap_or_else(|err| err.into_inner()).take()
+    }
+
+    /// Polls the future once. Run by a pool job.
+    ///
+    /// A panic is reported to the handle and then resumed so the worker records it as well.
+    pub(crate) fn run(self: Arc<Self>) {
+        if self.state.compare_exchange(SCHEDULED, RUNNING, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return;
+        }
+        let Some(mut future) = self.future().take() else {
+            return;
+        };
+        let waker = Waker::from(Arc::clone(&self));
+        let mut cx = Context::from_waker(&waker);
+
+        match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(&mut cx))) {
+            Ok(Poll::Pending) => {
+                *self.future() = Some(future);
+                match self.state.compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => {}
+                    Err(NOTIFIED) => {
+                        if self.state.compare_exchange(NOTIFIED, SCHEDULED, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                            self.schedule();
+                        }
+                    }
+                    // Cancelled by the pool shutting down while this poll ran.
+                    Err(_) => self.cancel(),
+                }
+            }
+            Ok(Poll::Ready(value)) => {
+                self.state.store(DONE, Ordering::SeqCst);
+                drop(future);
+                if let Some(completer) = self.completer() {
+                    completer.run(|| value);
+                }
+            }
+            Err(payload) => {
+                self.state.store(DONE, Ordering::SeqCst);
+                drop(future);
+                match self.completer() {
+                    Some(completer) => completer.run(|| panic::resume_unwind(payload)),
+                    None => panic::resume_unwind(payload),
+                }
+            }
+        }
+    }
+
+    /// Queues a poll. Wakeups are not subject to the queue capacity, since the future was
+    /// already accepted; if the pool is gone the future is dropped, cancelling its handle.
+    fn schedule(self: &Arc<Self>) {
+        let Some(shared) = self.shared.upgrade() else {
+            return self.cancel();
+        };
+        let task = Arc::clone(self);
+        let job = shared.new_job(move || task.run(), Priority::Normal);
+        match shared.queue.requeue(job) {
+            Ok(()) => shared.grow(),
+            Err(job) => {
+                drop(job);
+                self.cancel();
+            }
+        }
+    }
+
+}
+
+/// A spawned future, as registered with the pool so shutdown can cancel it.
+pub(crate) trait Spawned: Send + Sync {
+    /// Drops the future and cancels its handle, unless it has already completed. A poll
+    /// running at the same time finishes, but its output is discarded.
+    fn cancel(&self);
+}
+
+impl<T: Send + 'static> Spawned for FutureTask<T> {
+    fn cancel(&self) {
+        self.state.store(DONE, Ordering::SeqCst);
+        let future = self.future().take();
+        drop(future);
+        drop(self.completer());
+    }
+}
+
+impl<T: Send + 'static> Wake for FutureTask<T> {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self.state.compare_exchange(state, next, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) if next == SCHEDULED => return self.schedule(),
+                Ok(_) => return,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+}
+
+/// Wakes the thread blocked in `block_on`.
+struct Unparker(Thread);
+
+impl Wake for Unparker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the calling thread, parking it while the future is pending.
+///
+/// Use it to call async code from blocking code, for example inside a pool job or on the
+/// result of an `async fn`. The future runs on the calling thread only; use
+/// `PoolHandle::spawn_future` to run it on the pool. Called from a pool job, it keeps the
+/// worker busy until the future completes.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unparker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+            return value;
+        }
+        thread::park();
+    }
+}
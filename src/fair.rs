@@ -0,0 +1,133 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, Weak};
+
+use crate::{PoolError, Shared};
+use crate::worker::Task;
+
+/// Per key queues of `ThreadPool::execute_fair`, served by weighted round robin.
+///
+/// Every fair job puts a ticket in the pool's queue. The ticket does not run a particular job:
+/// the worker that takes it runs the next job in round robin order, so a key with many queued
+/// jobs gets one turn per round like any other key instead of a turn per queued job.
+pub(crate) struct Fair {
+    state: Mutex<State>,
+    /// Maximum number of queued jobs per key.
+    capacity: Option<usize>,
+}
+
+#[derive(Default)]
+struct State {
+    flows: HashMap<u64, Flow>,
+    /// Keys with queued jobs, the one to serve next in front.
+    ring: VecDeque<u64>,
+    /// Weights other than 1, by key.
+    weights: HashMap<u64, usize>,
+}
+
+#[derive(Default)]
+struct Flow {
+    jobs: VecDeque<Task>,
+    /// Jobs run in the key's current turn.
+    served: usize,
+}
+
+impl Fair {
+    pub(crate) fn new(capacity: Option<usize>) -> Fair {
+        Fair { state: Mutex::default(), capacity }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Queues `task` for `key` after `ticket` has queued the ticket that will run a fair job.
+    ///
+    /// The ticket is queued with the lock held so it cannot be taken before the job is queued.
+    pub(crate) fn push(&self, key: u64, task: Task, ticket: impl FnOnce() -> Result<(), PoolError>) -> Result<(), PoolError> {
+        let mut state = self.lock();
+        let queued = state.flows.get(&key).map_or(0, |flow| flow.jobs.len());
+        if self.capacity.is_some_and(|capacity| queued >= capacity) {
+            return Err(PoolError::QueueFull);
+        }
+        ticket()?;
+
+        let flow = state.flows.entry(key).or_default();
+        flow.jobs.push_back(task);
+        if flow.jobs.len() == 1 {
+            state.ring.push_back(key);
+        }
+        Ok(())
+    }
+
+    /// Takes the job to run for a ticket. The key in front of the ring runs up to its weight
+    /// of jobs in a row, then moves to the back.
+    pub(crate) fn next(&self) -> Option<Task> {
+        let mut state = self.lock();
+        let key = *state.ring.front()?;
+        let weight = state.weights.get(&key).copied().unwrap_or(1);
+
+        let flow = state.flows.get_mut(&key)?;
+        let task = flow.jobs.pop_front();
+        flow.served += 1;
+        if flow.jobs.is_empty() {
+            state.flows.remove(&key);
+            state.ring.pop_front();
+        } else if flow.served >= weight {
+            flow.served = 0;
+            state.ring.rotate_left(1);
+        }
+        task
+    }
+
+    pub(crate) fn set_weight(&self, key: u64, weight: usize) {
+        let mut state = self.lock();
+        match weight {
+            1 => state.weights.remove(&key),
+            _ => state.weights.insert(key, weight),
+        };
+    }
+
+    /// Returns the number of queued jobs of `key`.
+    pub(crate) fn len(&self, key: u64) -> usize {
+        self.lock().flows.get(&key).map_or(0, |flow| flow.jobs.len())
+    }
+}
+
+/// The job a fair job puts in the pool's queue. Run, it runs the next fair job in round robin
+/// order. Dropped without running, for example when `RejectionPolicy::DiscardOldest` evicts it
+/// or a shutdown abandons the queue, it discards the next fair job instead, so no fair job is
+/// left in `Fair` without a ticket to run it.
+pub(crate) struct Ticket {
+    shared: Weak<Shared>,
+    used: bool,
+}
+
+impl Ticket {
+    pub(crate) fn new(shared: Weak<Shared>) -> Ticket {
+        Ticket { shared, used: false }
+    }
+
+    pub(crate) fn run(mut self) {
+        if let Some(task) = self.take() {
+            task();
+        }
+    }
+
+    /// Drops a ticket that was not queued, leaving the fair jobs alone.
+    pub(crate) fn reject(mut self) {
+        self.used = true;
+    }
+
+    fn take(&mut self) -> Option<Task> {
+        self.used = true;
+        self.shared.upgrade()?.fair.next()
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if !self.used {
+            drop(self.take());
+        }
+    }
+}
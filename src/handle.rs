@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::panic::panic_message;
+
+/// Reason a `JobHandle` could not return the job's value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    /// The job panicked; carries the panic message.
+    Panicked(String),
+    /// The job was dropped without running.
+    Cancelled,
+    /// The job's outcome was already returned by an earlier `try_join` or `join_timeout`.
+    Taken,
+    /// `join_timeout` elapsed before the job finished.
+    Timeout,
+    /// `try_join` was called before the job finished.
+    Pending,
+}
+
+impl Display for JoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Panicked(msg) => write!(f, "job panicked: {msg}"),
+            JoinError::Cancelled => f.write_str("job was cancelled"),
+            JoinError::Taken => f.write_str("job result was already taken"),
+            JoinError::Timeout => f.write_str("timed out waiting for job"),
+            JoinError::Pending => f.write_str("job has not finished yet"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+enum State<T> {
+    Pending,
+    Done(Result<T, JoinError>),
+    Taken,
+}
+
+struct Packet<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+}
+
+impl<T> Packet<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Handle to the result of a job passed to `ThreadPool::submit`.
+pub struct JobHandle<T> {
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Blocks until the job finishes and returns its value.
+    pub fn join(self) -> Result<T, JoinError> {
+        let mut state = self.packet.lock();
+        while let State::Pending = *state {
+            state = self.packet.ready.wait(state).unwrap_or_else(|err| err.into_inner());
+        }
+        take(&mut state)
+    }
+
+    /// Blocks for at most `timeout` waiting for the job to finish.
+    ///
+    /// Returns `JoinError::Timeout` if the job is still running; the handle can be joined again.
+    pub fn join_timeout(&self, timeout: Duration) -> Result<T, JoinError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.packet.lock();
+        while let State::Pending = *state {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(JoinError::Timeout);
+            }
+            state = self.packet.ready.wait_timeout(state, remaining)
+                .unwrap_or_else(|err| err.into_inner()).0;
+        }
+        take(&mut state)
+    }
+
+    /// Returns the job's value without blocking, or `JoinError::Pending` if it is still running.
+    pub fn try_join(&self) -> Result<T, JoinError> {
+        let mut state = self.packet.lock();
+        match *state {
+            State::Pending => Err(JoinError::Pending),
+            _ => take(&mut state),
+        }
+    }
+
+    /// Returns `true` once the job has finished, panicked or been dropped.
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.packet.lock(), State::Pending)
+    }
+}
+
+fn take<T>(state: &mut State<T>) -> Result<T, JoinError> {
+    match std::mem::replace(state, State::Taken) {
+        State::Done(result) => result,
+        _ => Err(JoinError::Taken),
+    }
+}
+
+/// Producer side of a `JobHandle`, moved into the job.
+///
+/// Dropping it without completing, e.g. when the job is abandoned, cancels the handle.
+pub(crate) struct Completer<T> {
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> Completer<T> {
+    /// Runs `f` and publishes the outcome to the handle.
+    ///
+    /// A panic is reported to the handle and then resumed so the worker records it as well.
+    pub(crate) fn run<F>(self, f: F)
+        where F: FnOnce() -> T,
+    {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => self.complete(Ok(value)),
+            Err(payload) => {
+                self.complete(Err(JoinError::Panicked(panic_message(payload.as_ref()))));
+                panic::resume_unwind(payload);
+            }
+        }
+    }
+
+    fn complete(&self, result: Result<T, JoinError>) {
+        let mut state = self.packet.lock();
+        if let State::Pending = *state {
+            *state = State::Done(result);
+            self.packet.ready.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        self.complete(Err(JoinError::Cancelled));
+    }
+}
+
+/// Creates a connected `Completer` and `JobHandle`.
+pub(crate) fn pair<T>() -> (Completer<T>, JobHandle<T>) {
+    let packet = Arc::new(Packet { state: Mutex::new(State::Pending), ready: Condvar::new() });
+
+    (Completer { packet: Arc::clone(&packet) }, JobHandle { packet })
+}
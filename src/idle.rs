@@ -0,0 +1,68 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Counts jobs that are queued or running, so callers can wait for the pool to go idle.
+#[derive(Debug, Default)]
+pub(crate) struct Idle {
+    outstanding: AtomicUsize,
+    /// Number of callers in `wait`. A waiter increments it before checking `outstanding`, and
+    /// the last job decrements `outstanding` before checking it, so one of them sees the other.
+    waiters: AtomicUsize,
+    lock: Mutex<()>,
+    /// Signalled when `outstanding` drops to 0.
+    idle: Condvar,
+}
+
+/// Held by a job from submission until it has run or been discarded.
+#[derive(Debug)]
+pub(crate) struct Outstanding {
+    idle: Arc<Idle>,
+}
+
+impl Drop for Outstanding {
+    fn drop(&mut self) {
+        if self.idle.outstanding.fetch_sub(1, Ordering::SeqCst) == 1 && self.idle.waiters.load(Ordering::SeqCst) > 0 {
+            let _lock = self.idle.lock.lock().unwrap_or_else(|err| err.into_inner());
+            self.idle.idle.notify_all();
+        }
+    }
+}
+
+/// Counts a caller of `Idle::wait` until it returns.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Idle {
+    pub(crate) fn track(self: &Arc<Self>) -> Outstanding {
+        self.outstanding.fetch_add(1, Ordering::SeqCst);
+        Outstanding { idle: Arc::clone(self) }
+    }
+
+    /// Blocks until no job is outstanding or `deadline` passes.
+    ///
+    /// Returns `true` if the pool went idle.
+    pub(crate) fn wait(&self, deadline: Option<Instant>) -> bool {
+        let mut lock = self.lock.lock().unwrap_or_else(|err| err.into_inner());
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let _waiting = Waiting(&self.waiters);
+        while self.outstanding.load(Ordering::SeqCst) > 0 {
+            lock = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return false;
+                    }
+                    self.idle.wait_timeout(lock, remaining).unwrap_or_else(|err| err.into_inner()).0
+                }
+                None => self.idle.wait(lock).unwrap_or_else(|err| err.into_inner()),
+            };
+        }
+        true
+    }
+}
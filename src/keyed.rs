@@ -0,0 +1,81 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard, Weak};
+use std::sync::atomic::Ordering;
+
+use crate::Shared;
+use crate::worker::Job;
+
+/// Jobs of `ThreadPool::execute_keyed` waiting for the job ahead of them with the same key.
+///
+/// A key has a lane while one of its jobs is queued or running. Only that job is in the pool's
+/// queue; the others wait here in submission order and are queued one at a time as the job
+/// ahead of them finishes, so jobs of one key never overlap while different keys run in parallel.
+#[derive(Default)]
+pub(crate) struct Lanes {
+    lanes: Mutex<HashMap<u64, VecDeque<Job>>>,
+}
+
+impl Lanes {
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, VecDeque<Job>>> {
+        self.lanes.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Opens the lane of `key` and returns `item` if no job of the key is queued or running.
+    /// Otherwise queues the job built from `item` at the end of the lane.
+    pub(crate) fn admit<T>(&self, key: u64, item: T, into_job: impl FnOnce(T) -> Job) -> Option<T> {
+        let mut lanes = self.lock();
+        match lanes.get_mut(&key) {
+            Some(lane) => {
+                lane.push_back(into_job(item));
+                None
+            }
+            None => {
+                lanes.insert(key, VecDeque::new());
+                Some(item)
+            }
+        }
+    }
+
+    /// Returns the next job of `key`, or closes the lane if there is none.
+    fn next(&self, key: u64) -> Option<Job> {
+        let mut lanes = self.lock();
+        let job = lanes.get_mut(&key)?.pop_front();
+        if job.is_none() {
+            lanes.remove(&key);
+        }
+        job
+    }
+}
+
+/// Hashes the key of a keyed or fair job. Keys with equal hashes are treated as one key.
+pub(crate) fn hash_key<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Owned by a keyed job. When the job is dropped, whether it ran, panicked or was discarded,
+/// the next job of its lane is queued.
+pub(crate) struct Turn {
+    pub(crate) shared: Weak<Shared>,
+    pub(crate) key: u64,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        let Some(shared) = self.shared.upgrade() else {
+            return;
+        };
+        let Some(job) = shared.lanes.next(self.key) else {
+            return;
+        };
+        // A closed queue only takes the job from a worker, which drains or abandons it before
+        // exiting. Dropping it here passes the turn on to the job after it.
+        if let Err(job) = shared.queue.requeue(job) {
+            shared.metrics.discarded.fetch_add(1, Ordering::Relaxed);
+            drop(job);
+        }
+    }
+}
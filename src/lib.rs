@@ -1,197 +1,2716 @@
-use std::fmt::{Debug, Formatter};
-use std::sync::{Arc, mpsc, Mutex};
-use std::sync::mpsc::Receiver;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, mpsc, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+pub use affinity::Affinity;
+pub use builder::ThreadPoolBuilder;
+pub use cancel::CancellationToken;
+pub use error::PoolError;
+pub use executor::block_on;
+pub use handle::{JobHandle, JoinError};
+pub use log::{Level, Logger, Record, StderrLogger};
+pub use panic::JobPanic;
+pub use priority::{Priority, QueueLengths};
+pub use queue::TryExecuteError;
+pub use rejection::{RejectionPolicy, RejectionStats};
+pub use scope::Scope;
+pub use shutdown::{ShutdownPolicy, ShutdownReport, WorkerReport};
+pub use state::StatePool;
+pub use stats::{Histogram, PoolStats, RetiredWorkers, WorkerStats};
+pub use timer::ScheduledHandle;
+
+use builder::Hook;
+use executor::{FutureTask, Spawned};
+use fair::{Fair, Ticket};
+use idle::Idle;
+use keyed::{Lanes, Turn};
+use panic::PANIC_LOG_CAPACITY;
+use queue::{PushError, Queue};
+use rejection::RejectionCounters;
+use stats::Metrics;
+use supervisor::Event;
+use timer::Timer;
+use watchdog::TimeLimits;
+use worker::{Job, Worker, WorkerCounters};
+
+mod affinity;
+mod builder;
+mod cancel;
+mod error;
+mod executor;
+mod fair;
+mod handle;
+mod idle;
+mod keyed;
+mod log;
+mod panic;
+mod parallel;
+mod priority;
+mod queue;
+mod rejection;
+mod scope;
+mod shutdown;
+mod state;
+mod stats;
+mod supervisor;
+mod timer;
+mod watchdog;
+mod worker;
+
+/// State shared by the pool, its workers and the supervisor.
+struct Shared {
+    queue: Queue,
+    /// Jobs of `execute_fair`, by key.
+    fair: Fair,
+    /// Jobs of `execute_keyed` waiting for the job ahead of them with the same key.
+    lanes: Lanes,
+    rejection_policy: RejectionPolicy,
+    rejections: RejectionCounters,
+    /// Set when the queue should be discarded instead of drained.
+    abandon: AtomicBool,
+    /// Set once the pool stops accepting jobs; exited workers are no longer replaced.
+    shutting_down: AtomicBool,
+    /// Id of the next job, which is also the number of jobs accepted so far.
+    next_job_id: AtomicU64,
+    next_worker_id: AtomicUsize,
+    min_size: usize,
+    max_size: usize,
+    /// How long an idle worker above `min_size` waits for a job before retiring.
+    /// `None` for a fixed size pool.
+    keep_alive: Option<Duration>,
+    workers: Mutex<Vec<Worker>>,
+    /// Counters of the workers removed from `workers` when they retired.
+    retired: Mutex<RetiredWorkers>,
+    /// Counters of the workers the watchdog removed from `workers`. Kept rather than summed,
+    /// since their threads may still finish the job they were stuck in.
+    lost: Mutex<Vec<Arc<WorkerCounters>>>,
+    /// Signalled by the supervisor every time it reaps a worker.
+    exited: Condvar,
+    events: mpsc::Sender<Event>,
+    panics: Mutex<VecDeque<JobPanic>>,
+    /// Cancelled when the pool starts shutting down.
+    cancellation: CancellationToken,
+    /// Futures of `spawn_future`, cancelled at shutdown if they have not completed by then.
+    futures: Mutex<Vec<Weak<dyn Spawned>>>,
+    timer: Timer,
+    metrics: Metrics,
+    thread_name_prefix: String,
+    stack_size: Option<usize>,
+    on_thread_start: Option<Hook>,
+    on_thread_stop: Option<Hook>,
+    logger: Option<Arc<dyn Logger>>,
+    time_limits: TimeLimits,
+    idle: Arc<Idle>,
+    affinity: Affinity,
+    /// Cores the process may run on, looked up only if `affinity` asks for pinning.
+    available_cpus: Vec<usize>,
+    /// Reference point of `clock`.
+    created: Instant,
 }
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Result<Worker, std::io::Error> {
-        let builder = thread::Builder::new();
+impl Shared {
+    fn new(config: &ThreadPoolBuilder, events: mpsc::Sender<Event>) -> Shared {
+        Shared {
+            queue: Queue::new(config.queue_capacity),
+            fair: Fair::new(config.fair_capacity),
+            lanes: Lanes::default(),
+            rejection_policy: config.rejection_policy,
+            rejections: RejectionCounters::default(),
+            abandon: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            next_job_id: AtomicU64::new(0),
+            next_worker_id: AtomicUsize::new(0),
+            min_size: config.size,
+            max_size: config.max_size.unwrap_or(config.size),
+            keep_alive: config.max_size.filter(|max_size| *max_size > config.size).map(|_| config.keep_alive),
+            workers: Mutex::new(Vec::new()),
+            retired: Mutex::default(),
+            lost: Mutex::default(),
+            exited: Condvar::new(),
+            events,
+            panics: Mutex::new(VecDeque::with_capacity(PANIC_LOG_CAPACITY)),
+            cancellation: CancellationToken::new(),
+            futures: Mutex::new(Vec::new()),
+            timer: Timer::default(),
+            metrics: Metrics::default(),
+            thread_name_prefix: config.thread_name_prefix.clone(),
+            stack_size: config.stack_size,
+            on_thread_start: config.on_thread_start.clone(),
+            on_thread_stop: config.on_thread_stop.clone(),
+            logger: config.logger.clone(),
+            time_limits: config.time_limits,
+            idle: Arc::default(),
+            affinity: config.affinity.clone(),
+            available_cpus: match config.affinity {
+                Affinity::None => Vec::new(),
+                _ => affinity::available().unwrap_or_default(),
+            },
+            created: Instant::now(),
+        }
+    }
+
+    fn new_job<F>(&self, f: F, priority: Priority) -> Job
+        where F: FnOnce() + Send + 'static,
+    {
+        let id = self.next_job_id.fetch_add(1, Ordering::Relaxed);
+        Job { id, priority, queued_at: Instant::now(), task: Box::new(f), outstanding: self.idle.track() }
+    }
 
-        let thread = builder.spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
+    /// Returns a builder for a pool thread named `{prefix}-{name}`.
+    fn thread_builder(&self, name: impl std::fmt::Display) -> thread::Builder {
+        thread::Builder::new().name(format!("{}-{}", self.thread_name_prefix, name))
+    }
 
-            match message {
-                Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
+    /// Pins the calling worker thread according to `affinity`.
+    ///
+    /// Returns the cores it was pinned to, `None` if it runs unpinned.
+    fn pin_worker(&self, id: usize) -> Option<Vec<usize>> {
+        if self.affinity == Affinity::None {
+            return None;
+        }
+        let Some(cores) = self.affinity.cores_for(id, &self.available_cpus) else {
+            self.log(Level::Warn, Some(id), None, format_args!("Requested cores are not available; running unpinned"));
+            return None;
+        };
 
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down");
-                    break;
-                }
+        match affinity::pin(&cores) {
+            Ok(()) => Some(cores),
+            Err(err) => {
+                self.log(Level::Warn, Some(id), None, format_args!("Cannot pin worker to cores {cores:?}: {err}"));
+                None
             }
-        })?;
+        }
+    }
 
-        Ok(Worker { id, thread: Some(thread) })
+    /// Nanoseconds since the pool was created.
+    fn clock(&self) -> u64 {
+        self.clock_at(Instant::now())
+    }
+
+    /// `instant` as nanoseconds since the pool was created.
+    fn clock_at(&self, instant: Instant) -> u64 {
+        stats::as_nanos(instant.saturating_duration_since(self.created))
+    }
+
+    /// Passes a record to the logger, if there is one and it accepts `level`.
+    fn log(&self, level: Level, worker_id: Option<usize>, job_id: Option<u64>, message: std::fmt::Arguments<'_>) {
+        if let Some(logger) = self.logger.as_ref().filter(|logger| logger.enabled(level)) {
+            logger.log(&Record { level, message, worker_id, job_id });
+        }
+    }
+
+    fn lock_workers(&self) -> MutexGuard<'_, Vec<Worker>> {
+        self.workers.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn lock_retired(&self) -> MutexGuard<'_, RetiredWorkers> {
+        self.retired.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn lock_lost(&self) -> MutexGuard<'_, Vec<Arc<WorkerCounters>>> {
+        self.lost.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+
+    /// Spawns a worker with a fresh id and adds it to `workers`.
+    fn spawn_worker(self: &Arc<Self>, workers: &mut Vec<Worker>) -> Result<(), std::io::Error> {
+        let id = self.next_worker_id.fetch_add(1, Ordering::Relaxed);
+        workers.push(Worker::new(id, Arc::clone(self))?);
+        Ok(())
+    }
+
+    /// Adds a worker if jobs are waiting with no idle worker to take them and the pool
+    /// is below its maximum size.
+    fn grow(self: &Arc<Self>) {
+        if self.keep_alive.is_none() || self.queue.is_paused() || self.queue.backlog() == 0 {
+            return;
+        }
+
+        let mut workers = self.lock_workers();
+        if self.shutting_down.load(Ordering::SeqCst) || live_workers(&workers) >= self.max_size {
+            return;
+        }
+        if let Err(err) = self.spawn_worker(&mut workers) {
+            self.log(Level::Error, None, None, format_args!("Cannot grow pool: {err}"));
+        }
+    }
+
+    /// Marks an idle worker as retired if the pool is above its minimum size.
+    ///
+    /// Returns `true` if the worker should exit.
+    fn retire(&self, id: usize) -> bool {
+        let mut workers = self.lock_workers();
+        if live_workers(&workers) <= self.min_size {
+            return false;
+        }
+
+        match workers.iter_mut().find(|worker| worker.id == id) {
+            Some(worker) => {
+                worker.retired = true;
+                true
+            }
+            None => false,
+        }
     }
-}
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+    fn record_panic(&self, panic: JobPanic) {
+        self.log(Level::Error, Some(panic.worker_id), Some(panic.job_id), format_args!("Job panicked: {}", panic.message));
 
-pub struct PoolCreationError {
-    msg: String,
+        let mut panics = self.panics.lock().unwrap_or_else(|err| err.into_inner());
+        if panics.len() == PANIC_LOG_CAPACITY {
+            panics.pop_front();
+        }
+        panics.push_back(panic);
+    }
 }
 
-impl Debug for PoolCreationError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(self.msg.as_str())
+/// Sums the counters of workers no longer in `Shared::workers`.
+fn sum_counters(counters: &[Arc<WorkerCounters>]) -> RetiredWorkers {
+    let mut sum = RetiredWorkers::default();
+    for counters in counters {
+        sum.add(counters);
     }
+    sum
+}
+
+/// Counts workers that are running and not retiring.
+fn live_workers(workers: &[Worker]) -> usize {
+    workers.iter().filter(|worker| worker.thread.is_some() && !worker.retired).count()
 }
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    handle: PoolHandle,
+    supervisor: Option<thread::JoinHandle<()>>,
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        self.stop(ShutdownPolicy::Drain, None);
+    }
+}
 
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
-        }
+/// Creates a pool with 1 thread.
+///
+/// # Panic
+///
+/// Panics if the thread cannot be spawned.
+impl Default for ThreadPool {
+    fn default() -> Self {
+        ThreadPool::build(1).expect("Cannot create thread pool")
+    }
+}
+
+impl Deref for ThreadPool {
+    type Target = PoolHandle;
+
+    fn deref(&self) -> &PoolHandle {
+        &self.handle
     }
 }
 
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
-    /// The size is the number of threads in the pool.
+    /// The size is the number of threads in the pool. Workers that die are replaced
+    /// by a supervisor thread, so the pool keeps this size until it is shut down.
+    /// The job queue is unbounded; use `ThreadPool::builder` to limit it.
     ///
-    /// # Result<ThreadPool, PoolCreationError>
+    /// # Result<ThreadPool, PoolError>
     ///
-    /// The `build` function will return PoolCreationError if size is 0.
-    /// The function will return PoolCreationError in case of Worker couldn't be created
-    pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
-        if size == 0 {
-            return Err(PoolCreationError { msg: String::from("Pool size has to be greater than 0") });
-        }
-        let (sender, receiver) = mpsc::channel();
+    /// The `build` function will return `PoolError::ZeroSize` if size is 0
+    /// and `PoolError::Spawn` if a thread couldn't be created.
+    pub fn build(size: usize) -> Result<ThreadPool, PoolError> {
+        ThreadPoolBuilder::new().size(size).build()
+    }
+
+    /// Create a new ThreadPool whose jobs receive a per-thread state built by `init`.
+    ///
+    /// # Result<StatePool<S>, PoolError>
+    ///
+    /// The `build_with_state` function fails like `build`.
+    pub fn build_with_state<S, I>(size: usize, init: I) -> Result<StatePool<S>, PoolError>
+        where S: 'static,
+              I: Fn() -> S + Send + Sync + 'static,
+    {
+        ThreadPoolBuilder::new().size(size).build_with_state(init)
+    }
 
-        let receiver = Arc::new(Mutex::new(receiver));
+    /// Create a builder to configure a ThreadPool.
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
 
-        let mut workers = Vec::with_capacity(size);
+    /// Create a new ThreadPool with 1 thread.
+    ///
+    /// # Result<ThreadPool, PoolError>
+    ///
+    /// The `new` function will return `PoolError::Spawn` if it's not able to spawn a thread.
+    pub fn new() -> Result<ThreadPool, PoolError> {
+        ThreadPool::build(1)
+    }
 
-        for id in 0..size {
-            let worker_result = Worker::new(id, Arc::clone(&receiver));
-            if worker_result.is_err() {
-                return Err(PoolCreationError { msg: format!("Cannot create pool worker: {:?}", worker_result.unwrap_err()) });
-            }
-            workers.push(worker_result.unwrap());
+    /// Returns a handle to submit jobs to the pool, for example from inside its own jobs.
+    pub fn handle(&self) -> PoolHandle {
+        self.handle.clone()
+    }
+
+    /// Blocks until the queue is empty and no worker is running a job
+    ///
+    /// Unlike `shutdown`, the pool keeps running and accepts jobs afterwards. Jobs submitted
+    /// while waiting are waited for too. Scheduled jobs that are not due yet do not count.
+    ///
+    /// Calling `wait_idle` from a job of the same pool never returns, since that job is running.
+    pub fn wait_idle(&self) {
+        self.shared.idle.wait(None);
+    }
+
+    /// Blocks until the pool is idle, like `wait_idle`, or until `timeout` elapses.
+    ///
+    /// Returns `true` if the pool went idle.
+    pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+        self.shared.idle.wait(Some(Instant::now() + timeout))
+    }
+
+    /// Stops workers from picking up jobs until `resume` is called
+    ///
+    /// Running jobs finish normally, then the workers wait. Jobs can still be submitted and stay
+    /// in the queue, subject to its capacity and `RejectionPolicy`, and scheduled jobs are still
+    /// queued when they are due. `wait_idle` does not return while queued jobs wait for a resume.
+    ///
+    /// Shutting down resumes the pool, so `ShutdownPolicy::Drain` still runs the queued jobs.
+    pub fn pause(&self) {
+        self.shared.queue.pause();
+        self.shared.log(Level::Info, None, None, format_args!("Pool paused"));
+    }
+
+    /// Lets workers pick up jobs again after `pause`.
+    pub fn resume(&self) {
+        self.shared.queue.resume();
+        self.shared.log(Level::Info, None, None, format_args!("Pool resumed"));
+        self.shared.grow();
+    }
+
+    /// Returns `true` while the pool is paused.
+    pub fn is_paused(&self) -> bool {
+        self.shared.queue.is_paused()
+    }
+
+    /// Returns the number of jobs waiting in the queue at each priority.
+    pub fn queue_lengths(&self) -> QueueLengths {
+        self.shared.queue.lengths()
+    }
+
+    /// Returns a snapshot of the pool's queue, workers and job latencies.
+    ///
+    /// The counters are updated with relaxed atomics as jobs run, so the snapshot is cheap
+    /// but not taken at a single instant.
+    pub fn stats(&self) -> PoolStats {
+        let metrics = &self.shared.metrics;
+        let now = self.shared.clock();
+        let per_worker: Vec<WorkerStats> = self.shared.lock_workers().iter()
+            .filter(|worker| worker.thread.is_some())
+            .map(|worker| worker.stats(now))
+            .collect();
+
+        PoolStats {
+            affinity: self.shared.affinity.clone(),
+            paused: self.shared.queue.is_paused(),
+            queued: self.shared.queue.lengths(),
+            workers: per_worker.len(),
+            active_workers: per_worker.iter().filter(|worker| worker.active).count(),
+            submitted: self.shared.next_job_id.load(Ordering::Relaxed),
+            completed: metrics.completed.load(Ordering::Relaxed),
+            panicked: metrics.panicked.load(Ordering::Relaxed),
+            discarded: metrics.discarded.load(Ordering::Relaxed),
+            slow_jobs: metrics.slow_jobs.load(Ordering::Relaxed),
+            lost_workers: metrics.lost_workers.load(Ordering::Relaxed),
+            queue_wait: metrics.queue_wait.snapshot(),
+            execution: metrics.execution.snapshot(),
+            per_worker,
+            retired: *self.shared.lock_retired(),
+            lost: sum_counters(&self.shared.lock_lost()),
         }
+    }
 
-        Ok(ThreadPool { workers, sender: Some(sender) })
+    /// Returns how many jobs each `RejectionPolicy` outcome has handled.
+    pub fn rejections(&self) -> RejectionStats {
+        self.shared.rejections.snapshot()
     }
 
-    /// Create a new ThreadPool with 1 thread.
+    /// Returns the most recent job panics, oldest first.
     ///
-    /// # Result<ThreadPool, PoolCreationError>
+    /// At most 64 records are kept.
+    pub fn panics(&self) -> Vec<JobPanic> {
+        self.shared.panics.lock().unwrap_or_else(|err| err.into_inner()).iter().cloned().collect()
+    }
+
+    /// Shuts the pool down, draining the queue, and waits up to `timeout` for the workers.
     ///
-    /// The `new` function will return PoolCreationError it's not able to spawn a thread.
-    pub fn new() -> ThreadPool {
-        ThreadPool::build(1).unwrap()
+    /// Equivalent to `shutdown_with(ShutdownPolicy::Drain, timeout)`.
+    pub fn shutdown(&mut self, timeout: Duration) -> ShutdownReport {
+        self.shutdown_with(ShutdownPolicy::Drain, timeout)
     }
 
-    /// Executes a function by sending it to the pool
+    /// Shuts the pool down and waits up to `timeout` for the workers to exit.
     ///
-    /// # Panic
-    pub fn execute<F>(&self, f: F)
-        where F: FnOnce() + Send + 'static,
-    {
-        let job = Box::new(f);
+    /// The pool stops accepting jobs immediately. Queued jobs are either run or discarded
+    /// according to `policy`. Workers still busy when the deadline passes are detached and
+    /// reported as unfinished, and the jobs still queued then are discarded and counted in
+    /// `ShutdownReport::abandoned`; a panicked worker is reported instead of propagating the panic.
+    /// Called from one of the pool's own jobs, it does not wait for the calling worker, which is
+    /// reported as unfinished as well.
+    ///
+    /// Calling `shutdown_with` on a pool that is already shut down returns an empty report.
+    pub fn shutdown_with(&mut self, policy: ShutdownPolicy, timeout: Duration) -> ShutdownReport {
+        self.stop(policy, Some(Instant::now() + timeout))
+    }
+
+    fn stop(&mut self, policy: ShutdownPolicy, deadline: Option<Instant>) -> ShutdownReport {
+        if policy == ShutdownPolicy::Abandon {
+            self.shared.abandon.store(true, Ordering::SeqCst);
+        }
+        self.shared.shutting_down.store(true, Ordering::SeqCst);
+        self.shared.cancellation.cancel();
+        self.shared.queue.close();
+        self.shared.timer.shutdown();
+
+        // A worker dropping the last owner of the pool in one of its jobs cannot wait for itself.
+        let caller = self.shared.queue.is_worker().then(|| thread::current().id());
+        let waited_for = |worker: &Worker| {
+            worker.thread.as_ref().is_some_and(|thread| Some(thread.thread().id()) != caller)
+        };
+        let mut workers = self.shared.lock_workers();
+        while workers.iter().any(waited_for) {
+            workers = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        break;
+                    }
+                    self.shared.exited.wait_timeout(workers, remaining)
+                        .unwrap_or_else(|err| err.into_inner()).0
+                }
+                None => self.shared.exited.wait(workers).unwrap_or_else(|err| err.into_inner()),
+            };
+        }
+
+        // Workers that missed the deadline must not go on running the queue once shutdown has
+        // returned, so whatever is left is discarded here.
+        let leftover = match workers.iter().any(waited_for) {
+            true => {
+                self.shared.abandon.store(true, Ordering::SeqCst);
+                self.shared.queue.drain()
+            }
+            false => Vec::new(),
+        };
+
+        let reports = workers.drain(..)
+            .map(|mut worker| {
+                self.shared.log(Level::Debug, Some(worker.id), None, format_args!("Shutting down worker"));
+
+                let report = worker.report();
+                // Detach a worker that missed the deadline.
+                drop(worker.thread.take());
+                report
+            })
+            .collect();
+        drop(workers);
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        let abandoned = leftover.len();
+        self.shared.metrics.discarded.fetch_add(abandoned as u64, Ordering::Relaxed);
+        drop(leftover);
+
+        // Futures still pending have no worker left to poll them.
+        let futures = std::mem::take(&mut *self.shared.futures.lock().unwrap_or_else(|err| err.into_inner()));
+        for task in futures.iter().filter_map(Weak::upgrade) {
+            task.cancel();
+        }
+
+        let _ = self.shared.events.send(Event::Shutdown);
+        if let Some(supervisor) = self.supervisor.take() {
+            let _ = supervisor.join();
+        }
+
+        ShutdownReport {
+            workers: reports,
+            abandoned,
+            retired: std::mem::take(&mut *self.shared.lock_retired()),
+            lost: sum_counters(&std::mem::take(&mut *self.shared.lock_lost())),
+        }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use std::ops::Deref;
-    use std::sync::{Arc, Mutex};
+/// A cloneable handle to submit jobs to a `ThreadPool`, returned by `ThreadPool::handle`.
+///
+/// Handles can be sent to other threads and into the pool's own jobs to schedule follow-up
+/// work. They do not keep the pool running: once it has been shut down or dropped, every
+/// submission fails with `PoolError::ShutDown`, scopes run their jobs on the calling thread,
+/// and `scope`, `map`, `for_each` and `reduce` still return. The `ThreadPool` derefs to its
+/// handle, so all of these functions are available on the pool as well.
+#[derive(Clone)]
+pub struct PoolHandle {
+    shared: Arc<Shared>,
+}
 
-    use super::*;
+impl PoolHandle {
+    /// Returns the current number of workers.
+    ///
+    /// For an elastic pool this is between the configured minimum and maximum size.
+    pub fn size(&self) -> usize {
+        live_workers(&self.shared.lock_workers())
+    }
 
-    #[test]
-    fn worker_create() -> Result<(), String> {
-        let (sender, receiver) = mpsc::channel();
+    /// Executes a function by sending it to the pool
+    ///
+    /// When the queue is at capacity the pool's `RejectionPolicy` decides what happens to the job.
+    /// A panic inside `f` is contained: it is recorded in `panics` and the worker keeps running.
+    ///
+    /// # Result<(), PoolError>
+    ///
+    /// The `execute` function will return `PoolError::ShutDown` if the pool has been shut down,
+    /// and `PoolError::QueueFull` if the queue is full and the policy is `RejectionPolicy::Abort`.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolError>
+        where F: FnOnce() + Send + 'static,
+    {
+        self.execute_with_priority(Priority::Normal, f)
+    }
 
-        let receiver = Arc::new(Mutex::new(receiver));
+    /// Executes a function by sending it to the pool with the given priority
+    ///
+    /// Workers take higher priority jobs first; a lower priority job that has been passed over
+    /// several times is taken next, so low priority work still makes progress under load.
+    ///
+    /// # Result<(), PoolError>
+    ///
+    /// The `execute_with_priority` function will return `PoolError::ShutDown` if the pool has been
+    /// shut down, and `PoolError::QueueFull` if the queue is full and the policy is `RejectionPolicy::Abort`.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<(), PoolError>
+        where F: FnOnce() + Send + 'static,
+    {
+        let deadline = match self.shared.rejection_policy {
+            RejectionPolicy::Block => None,
+            _ => Some(Instant::now()),
+        };
 
-        let id = 0;
+        match self.enqueue(f, priority, deadline) {
+            Ok(()) => Ok(()),
+            Err((_, PushError::Closed)) => Err(PoolError::ShutDown),
+            Err((f, PushError::Full)) => self.reject(f, priority),
+        }
+    }
 
-        let result = Worker::new(id, Arc::clone(&receiver));
+    fn reject<F>(&self, f: F, priority: Priority) -> Result<(), PoolError>
+        where F: FnOnce() + Send + 'static,
+    {
+        let rejections = &self.shared.rejections;
 
-        match result {
-            Ok(worker) if id == worker.id => {
-                Ok(())
+        match self.shared.rejection_policy {
+            RejectionPolicy::Block | RejectionPolicy::Abort => {
+                rejections.aborted.fetch_add(1, Ordering::Relaxed);
+                return Err(PoolError::QueueFull);
             }
-            Err(err) => {
-                Err(err.to_string())
+            RejectionPolicy::CallerRuns => {
+                rejections.caller_runs.fetch_add(1, Ordering::Relaxed);
+                f();
             }
-            Ok(_) => {
-                Err(format!("id from worker is not equal to {}", id))
+            RejectionPolicy::DiscardNewest => {
+                rejections.discarded_newest.fetch_add(1, Ordering::Relaxed);
+                drop(f);
+            }
+            RejectionPolicy::DiscardOldest => {
+                let evicted = self.shared.queue.push_evicting(f, |f| self.shared.new_job(f, priority));
+                match evicted {
+                    Ok(Some(evicted)) => {
+                        rejections.discarded_oldest.fetch_add(1, Ordering::Relaxed);
+                        self.shared.metrics.discarded.fetch_add(1, Ordering::Relaxed);
+                        drop(evicted);
+                    }
+                    Ok(None) => self.shared.grow(),
+                    Err(_) => return Err(PoolError::ShutDown),
+                }
             }
         }
+        Ok(())
     }
 
-    #[test]
-    fn thread_pool_create_with_threads() -> Result<(), String> {
-        let result = ThreadPool::build(3);
+    /// Executes a function if the queue has room, without blocking
+    ///
+    /// # Result<(), TryExecuteError<F>>
+    ///
+    /// The `try_execute` function will return `Full` with the job if the queue is at capacity,
+    /// and `ShutDown` with the job if the pool has been shut down.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), TryExecuteError<F>>
+        where F: FnOnce() + Send + 'static,
+    {
+        self.enqueue(f, Priority::Normal, Some(Instant::now())).map_err(|(f, err)| match err {
+            PushError::Full => TryExecuteError::Full(f),
+            PushError::Closed => TryExecuteError::ShutDown(f),
+        })
+    }
 
-        match result {
-            Ok(_) => {
-                Ok(())
-            }
-            Err(err) => {
-                Err(err.msg)
-            }
+    /// Executes a function, waiting up to `timeout` for room in the queue
+    ///
+    /// # Result<(), TryExecuteError<F>>
+    ///
+    /// The `execute_timeout` function will return `Timeout` with the job if the queue stayed at
+    /// capacity, and `ShutDown` with the job if the pool has been shut down.
+    pub fn execute_timeout<F>(&self, f: F, timeout: Duration) -> Result<(), TryExecuteError<F>>
+        where F: FnOnce() + Send + 'static,
+    {
+        self.enqueue(f, Priority::Normal, Some(Instant::now() + timeout)).map_err(|(f, err)| match err {
+            PushError::Full => TryExecuteError::Timeout(f),
+            PushError::Closed => TryExecuteError::ShutDown(f),
+        })
+    }
+
+    /// Executes a function after every job previously submitted with the same key
+    ///
+    /// Jobs with equal keys run one at a time in submission order, while jobs with different
+    /// keys run in parallel on the pool's workers. Only the oldest unfinished job of a key is
+    /// in the queue; the others wait for their turn outside of it and do not count against
+    /// its capacity. A job that panics, or is discarded by the `RejectionPolicy`, passes its
+    /// turn on to the next job of the key.
+    ///
+    /// Keys are compared by hash, so two keys with colliding hashes are serialized as well.
+    ///
+    /// # Result<(), PoolError>
+    ///
+    /// The `execute_keyed` function will return `PoolError::ShutDown` if the pool has been shut
+    /// down. When no other job of the key is waiting it fails like `execute` otherwise.
+    pub fn execute_keyed<K, F>(&self, key: K, f: F) -> Result<(), PoolError>
+        where K: Hash,
+              F: FnOnce() + Send + 'static,
+    {
+        if self.shared.shutting_down.load(Ordering::SeqCst) {
+            return Err(PoolError::ShutDown);
+        }
+
+        let turn = Turn { shared: Arc::downgrade(&self.shared), key: keyed::hash_key(&key) };
+        let key = turn.key;
+        let job = move || {
+            let _turn = turn;
+            f();
+        };
+        match self.shared.lanes.admit(key, job, |job| self.shared.new_job(job, Priority::Normal)) {
+            Some(job) => self.execute(job),
+            None => Ok(()),
         }
     }
 
-    #[test]
-    fn thread_pool_create_default() {
-        let result = ThreadPool::new();
+    /// Executes a function in the fair queue of `key`
+    ///
+    /// Workers take fair jobs from the keys with queued jobs in round robin order, so a key that
+    /// submits many jobs, such as a busy tenant or client address, does not delay the jobs of the
+    /// other keys behind all of its own. Each key runs as many jobs in a row as its weight, set
+    /// with `set_fair_weight`. Jobs of one key start in submission order but may overlap; use
+    /// `execute_keyed` for that.
+    ///
+    /// Fair jobs share the queue and workers with the other jobs at `Priority::Normal`, and
+    /// count against the queue capacity. They are limited per key as well by
+    /// `ThreadPoolBuilder::max_queued_per_key`. A fair job that does not fit is rejected
+    /// right away, whatever the `RejectionPolicy`. Evicting a fair job's place in the queue
+    /// with `RejectionPolicy::DiscardOldest`, or abandoning the queue at shutdown, discards
+    /// the next fair job to run.
+    ///
+    /// # Result<(), PoolError>
+    ///
+    /// The `execute_fair` function will return `PoolError::ShutDown` if the pool has been shut
+    /// down, and `PoolError::QueueFull` if the queue is full or the key already has the maximum
+    /// number of queued jobs.
+    pub fn execute_fair<K, F>(&self, key: K, f: F) -> Result<(), PoolError>
+        where K: Hash,
+              F: FnOnce() + Send + 'static,
+    {
+        if self.shared.shutting_down.load(Ordering::SeqCst) {
+            return Err(PoolError::ShutDown);
+        }
+
+        self.shared.fair.push(keyed::hash_key(&key), Box::new(f), || {
+            let ticket = Ticket::new(Arc::downgrade(&self.shared));
+            let into_job = |ticket: Ticket| self.shared.new_job(move || ticket.run(), Priority::Normal);
+            self.shared.queue.push(ticket, Some(Instant::now()), into_job).map_err(|(ticket, err)| {
+                // The fair job is not queued either.
+                ticket.reject();
+                match err {
+                    PushError::Full => PoolError::QueueFull,
+                    PushError::Closed => PoolError::ShutDown,
+                }
+            })
+        })?;
+        self.shared.grow();
+        Ok(())
+    }
 
-        assert_eq!(1usize, result.workers.len());
+    /// Sets how many fair jobs of `key` run in a row before the next key's turn. Defaults to 1.
+    ///
+    /// # Result<(), PoolError>
+    ///
+    /// The `set_fair_weight` function will return `PoolError::ZeroWeight` if `weight` is 0.
+    pub fn set_fair_weight<K: Hash>(&self, key: K, weight: usize) -> Result<(), PoolError> {
+        if weight == 0 {
+            return Err(PoolError::ZeroWeight);
+        }
+        self.shared.fair.set_weight(keyed::hash_key(&key), weight);
+        Ok(())
     }
 
-    #[test]
-    fn thread_pool_executes_work() {
-        // given
-        let m = Arc::new(Mutex::new(0));
+    /// Returns the number of fair jobs of `key` waiting to run.
+    pub fn fair_queue_len<K: Hash>(&self, key: K) -> usize {
+        self.shared.fair.len(keyed::hash_key(&key))
+    }
 
-        fn test_exec(m: Arc<Mutex<i32>>) {
-            *m.lock().unwrap() += 1;
+    fn enqueue<F>(&self, f: F, priority: Priority, deadline: Option<Instant>) -> Result<(), (F, PushError)>
+        where F: FnOnce() + Send + 'static,
+    {
+        let local = match priority {
+            Priority::Normal => self.shared.queue.push_local(f, |f| self.shared.new_job(f, priority)),
+            _ => Err(f),
+        };
+        if let Err(f) = local {
+            self.shared.queue.push(f, deadline, |f| self.shared.new_job(f, priority))?;
         }
+        self.shared.grow();
+        Ok(())
+    }
 
-        let pool = ThreadPool::new();
-        let to_execute_1 = Arc::clone(&m);
-        let to_execute_2 = Arc::clone(&m);
-        let to_execute_3 = Arc::clone(&m);
+    /// Executes a function on the pool once `delay` has elapsed
+    ///
+    /// The job is queued by a timer thread when it is due; the returned handle can cancel it.
+    ///
+    /// # Result<ScheduledHandle, PoolError>
+    ///
+    /// The `execute_after` function will return `PoolError::ShutDown` if the pool has been shut down,
+    /// and `PoolError::Spawn` if the timer thread cannot be started.
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> Result<ScheduledHandle, PoolError>
+        where F: FnOnce() + Send + 'static,
+    {
+        self.execute_at(Instant::now() + delay, f)
+    }
 
-        // when
-        pool.execute(|| {
-            test_exec(to_execute_1);
-        });
-        pool.execute(|| {
-            test_exec(to_execute_2);
-        });
-        pool.execute(|| {
-            test_exec(to_execute_3);
-        });
-        drop(pool);
+    /// Executes a function on the pool at `at`, or as soon as possible if it is in the past
+    ///
+    /// # Result<ScheduledHandle, PoolError>
+    ///
+    /// The `execute_at` function will return `PoolError::ShutDown` if the pool has been shut down,
+    /// and `PoolError::Spawn` if the timer thread cannot be started.
+    pub fn execute_at<F>(&self, at: Instant, f: F) -> Result<ScheduledHandle, PoolError>
+        where F: FnOnce() + Send + 'static,
+    {
+        Timer::schedule_once(&self.shared, at, Box::new(f))
+    }
 
-        // then
-        assert_eq!(3, *m.lock().unwrap().deref());
+    /// Executes a function on the pool every `period`, starting one period from now
+    ///
+    /// Runs are scheduled at a fixed rate from the first deadline. A run that is late because
+    /// the previous one overran starts immediately; runs never overlap. If a run panics no further
+    /// runs are scheduled and the handle reports the job as cancelled.
+    ///
+    /// # Result<ScheduledHandle, PoolError>
+    ///
+    /// The `execute_at_fixed_rate` function will return `PoolError::ZeroPeriod` if `period` is zero,
+    /// `PoolError::ShutDown` if the pool has been shut down,
+    /// and `PoolError::Spawn` if the timer thread cannot be started.
+    pub fn execute_at_fixed_rate<F>(&self, period: Duration, f: F) -> Result<ScheduledHandle, PoolError>
+        where F: FnMut() + Send + 'static,
+    {
+        if period.is_zero() {
+            return Err(PoolError::ZeroPeriod);
+        }
+
+        Timer::schedule_fixed_rate(&self.shared, period, Arc::new(Mutex::new(f)))
+    }
+
+    /// Runs a future on the pool and returns a handle to its output
+    ///
+    /// Each poll of the future runs as a job on a worker, counted in `stats` like any other.
+    /// While the future is pending it does not occupy a worker: waking it queues the next poll.
+    /// A panic while polling is reported as `JoinError::Panicked` by the handle, and the handle
+    /// is cancelled if the pool is shut down before the future completes.
+    ///
+    /// # Result<JobHandle<T>, PoolError>
+    ///
+    /// The `spawn_future` function fails like `execute`.
+    pub fn spawn_future<F, T>(&self, future: F) -> Result<JobHandle<T>, PoolError>
+        where F: Future<Output = T> + Send + 'static,
+              T: Send + 'static,
+    {
+        let (task, handle) = FutureTask::new(future, &self.shared);
+        {
+            let mut futures = self.shared.futures.lock().unwrap_or_else(|err| err.into_inner());
+            // Forget completed futures before growing, which keeps the registration amortized O(1).
+            if futures.len() == futures.capacity() {
+                futures.retain(|task| task.strong_count() > 0);
+            }
+            futures.push(Arc::downgrade(&task) as Weak<dyn Spawned>);
+        }
+
+        self.execute(move || task.run())?;
+        Ok(handle)
+    }
+
+    /// Executes a function on the pool and returns a handle to its result
+    ///
+    /// A panic inside `f` is caught and reported as `JoinError::Panicked` by the handle.
+    ///
+    /// # Result<JobHandle<T>, PoolError>
+    ///
+    /// The `submit` function fails like `execute`.
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, PoolError>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static,
+    {
+        let (completer, handle) = handle::pair();
+
+        self.execute(move || completer.run(f))?;
+
+        Ok(handle)
+    }
+
+    /// Runs `f` with a `Scope` whose jobs may borrow from the caller's stack
+    ///
+    /// Returns once `f` and every job executed on the scope have finished. While waiting, the
+    /// calling thread runs scoped jobs no worker has picked up yet, so a scope can also be
+    /// used from inside a job of the same pool.
+    ///
+    /// # Panic
+    ///
+    /// If `f` or any scoped job panics, the panic is resumed on the caller after all jobs
+    /// have finished. A panic in `f` takes precedence over one in a job.
+    ///
+    /// ```
+    /// use web_server_rust_book::ThreadPool;
+    ///
+    /// let pool = ThreadPool::build(2).unwrap();
+    /// let mut halves = [vec![1, 2], vec![3, 4]];
+    ///
+    /// pool.scope(|s| {
+    ///     for half in halves.iter_mut() {
+    ///         s.execute(move || half.iter_mut().for_each(|n| *n *= 10));
+    ///     }
+    /// });
+    ///
+    /// assert_eq!([vec![10, 20], vec![30, 40]], halves);
+    /// ```
+    pub fn scope<'env, F, T>(&'env self, f: F) -> T
+        where F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        scope::run(self, f)
+    }
+
+    /// Applies `f` to every item on the pool and returns the results in the order of `items`
+    ///
+    /// The items are split into consecutive chunks, a few per worker, and each chunk is mapped
+    /// by one job. A panic in `f` is resumed on the caller once all chunks have finished.
+    pub fn map<I, F, T>(&self, items: I, f: F) -> Vec<T>
+        where I: IntoIterator,
+              I::Item: Send,
+              F: Fn(I::Item) -> T + Sync,
+              T: Send,
+    {
+        let chunks = parallel::split(items, self.size());
+        let mut results: Vec<Vec<T>> = chunks.iter().map(|_| Vec::new()).collect();
+        let f = &f;
+
+        self.scope(|s| {
+            for (chunk, result) in chunks.into_iter().zip(results.iter_mut()) {
+                s.execute(move || *result = chunk.into_iter().map(f).collect());
+            }
+        });
+
+        results.into_iter().flatten().collect()
+    }
+
+    /// Calls `f` with every item on the pool and waits for all calls to finish
+    ///
+    /// Items are processed in chunks like `map`. A panic in `f` is resumed on the caller
+    /// once all chunks have finished.
+    pub fn for_each<I, F>(&self, items: I, f: F)
+        where I: IntoIterator,
+              I::Item: Send,
+              F: Fn(I::Item) + Sync,
+    {
+        let f = &f;
+
+        self.scope(|s| {
+            for chunk in parallel::split(items, self.size()) {
+                s.execute(move || chunk.into_iter().for_each(f));
+            }
+        });
+    }
+
+    /// Combines all items with `op` on the pool
+    ///
+    /// Each chunk is folded starting from `identity()`, then the chunk results are combined in
+    /// the order of `items`, so `op` has to be associative but not commutative. Returns
+    /// `identity()` if there are no items.
+    pub fn reduce<I, ID, OP>(&self, items: I, identity: ID, op: OP) -> I::Item
+        where I: IntoIterator,
+              I::Item: Send,
+              ID: Fn() -> I::Item + Sync,
+              OP: Fn(I::Item, I::Item) -> I::Item + Sync,
+    {
+        let (identity, op) = (&identity, &op);
+
+        self.map(parallel::split(items, self.size()), |chunk| chunk.into_iter().fold(identity(), op))
+            .into_iter()
+            .fold(identity(), op)
+    }
+
+    /// Executes a function that can be cancelled through `token`
+    ///
+    /// `f` is given a token that is cancelled when `token` or the pool's `cancellation_token`
+    /// is, and should check it while it runs. If the token is already cancelled when a worker
+    /// picks the job up, `f` is not run.
+    ///
+    /// # Result<(), PoolError>
+    ///
+    /// The `execute_with_token` function fails like `execute`.
+    pub fn execute_with_token<F>(&self, token: &CancellationToken, f: F) -> Result<(), PoolError>
+        where F: FnOnce(&CancellationToken) + Send + 'static,
+    {
+        let job_token = CancellationToken::new();
+        token.link(&job_token);
+        self.shared.cancellation.link(&job_token);
+
+        self.execute(move || {
+            if !job_token.is_cancelled() {
+                f(&job_token);
+            }
+        })
+    }
+
+    /// Returns the pool-wide token, cancelled as soon as the pool starts shutting down.
+    ///
+    /// Tokens created from it with `child_token` are cancelled at shutdown as well.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.shared.cancellation.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Deref;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn worker_create() -> Result<(), String> {
+        let (events, _) = mpsc::channel();
+
+        let shared = Arc::new(Shared::new(&ThreadPoolBuilder::new(), events));
+
+        let id = 0;
+
+        let result = Worker::new(id, Arc::clone(&shared));
+
+        match result {
+            Ok(worker) if id == worker.id => {
+                Ok(())
+            }
+            Err(err) => {
+                Err(err.to_string())
+            }
+            Ok(_) => {
+                Err(format!("id from worker is not equal to {}", id))
+            }
+        }
+    }
+
+    #[test]
+    fn thread_pool_create_with_threads() -> Result<(), String> {
+        let result = ThreadPool::build(3);
+
+        match result {
+            Ok(_) => {
+                Ok(())
+            }
+            Err(err) => {
+                Err(err.to_string())
+            }
+        }
+    }
+
+    #[test]
+    fn thread_pool_create_default() {
+        let result = ThreadPool::new().unwrap();
+
+        assert_eq!(1usize, result.shared.lock_workers().len());
+    }
+
+    #[test]
+    fn thread_pool_executes_work() {
+        // given
+        let m = Arc::new(Mutex::new(0));
+
+        fn test_exec(m: Arc<Mutex<i32>>) {
+            *m.lock().unwrap() += 1;
+        }
+
+        let pool = ThreadPool::new().unwrap();
+        let to_execute_1 = Arc::clone(&m);
+        let to_execute_2 = Arc::clone(&m);
+        let to_execute_3 = Arc::clone(&m);
+
+        // when
+        pool.execute(|| {
+            test_exec(to_execute_1);
+        }).unwrap();
+        pool.execute(|| {
+            test_exec(to_execute_2);
+        }).unwrap();
+        pool.execute(|| {
+            test_exec(to_execute_3);
+        }).unwrap();
+        drop(pool);
+
+        // then
+        assert_eq!(3, *m.lock().unwrap().deref());
+    }
+
+    #[test]
+    fn thread_pool_shutdown_drains_queue() {
+        // given
+        let m = Arc::new(Mutex::new(0));
+        let mut pool = ThreadPool::new().unwrap();
+
+        for _ in 0..5 {
+            let m = Arc::clone(&m);
+            pool.execute(move || *m.lock().unwrap() += 1).unwrap();
+        }
+
+        // when
+        let report = pool.shutdown(Duration::from_secs(5));
+
+        // then
+        assert!(report.is_finished());
+        assert_eq!(5, report.completed());
+        assert_eq!(0, report.abandoned());
+        assert_eq!(5, *m.lock().unwrap().deref());
+    }
+
+    #[test]
+    fn thread_pool_shutdown_abandons_queue() {
+        // given
+        let m = Arc::new(Mutex::new(0));
+        let mut pool = ThreadPool::new().unwrap();
+        let (started_sender, started) = mpsc::channel();
+
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }).unwrap();
+        started.recv().unwrap();
+        for _ in 0..3 {
+            let m = Arc::clone(&m);
+            pool.execute(move || *m.lock().unwrap() += 1).unwrap();
+        }
+
+        // when
+        let report = pool.shutdown_with(ShutdownPolicy::Abandon, Duration::from_secs(5));
+
+        // then
+        assert!(report.is_finished());
+        assert_eq!(1, report.completed());
+        assert_eq!(3, report.abandoned());
+        assert_eq!(0, *m.lock().unwrap().deref());
+    }
+
+    #[test]
+    fn thread_pool_shutdown_times_out() {
+        let mut pool = ThreadPool::new().unwrap();
+        pool.execute(|| thread::sleep(Duration::from_millis(500))).unwrap();
+
+        let report = pool.shutdown(Duration::from_millis(10));
+
+        assert!(!report.is_finished());
+        assert_eq!(1, report.workers.len());
+    }
+
+    #[test]
+    fn thread_pool_shutdown_discards_queue_after_deadline() {
+        // given
+        let m = Arc::new(Mutex::new(0));
+        let (mut pool, release) = blocked_pool_with(ThreadPool::builder().size(1));
+        for _ in 0..3 {
+            let m = Arc::clone(&m);
+            pool.execute(move || *m.lock().unwrap() += 1).unwrap();
+        }
+
+        // when
+        let report = pool.shutdown(Duration::from_millis(10));
+        drop(release);
+        thread::sleep(Duration::from_millis(50));
+
+        // then
+        assert!(!report.is_finished());
+        assert_eq!(3, report.abandoned());
+        assert_eq!(3, pool.stats().discarded);
+        assert_eq!(0, *m.lock().unwrap().deref());
+    }
+
+    #[test]
+    fn thread_pool_shutdown_reports_panic() {
+        let mut pool = ThreadPool::new().unwrap();
+        pool.execute(|| panic!("job failed")).unwrap();
+
+        let report = pool.shutdown(Duration::from_secs(5));
+
+        assert!(report.is_finished());
+        assert_eq!(1, report.panicked());
+    }
+
+    #[test]
+    fn thread_pool_execute_after_shutdown() {
+        let mut pool = ThreadPool::new().unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        assert!(matches!(pool.execute(|| {}), Err(PoolError::ShutDown)));
+        assert!(matches!(pool.submit(|| 1), Err(PoolError::ShutDown)));
+    }
+
+    #[test]
+    fn thread_pool_submit_returns_value() {
+        let pool = ThreadPool::build(2).unwrap();
+
+        let handles: Vec<_> = (0..4).map(|i| pool.submit(move || i * 2).unwrap()).collect();
+        let results: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+        assert_eq!(vec![0, 2, 4, 6], results);
+    }
+
+    #[test]
+    fn thread_pool_submit_reports_panic() {
+        let pool = ThreadPool::new().unwrap();
+
+        let handle = pool.submit(|| -> i32 { panic!("bad request") }).unwrap();
+
+        assert_eq!(Err(JoinError::Panicked(String::from("bad request"))), handle.join());
+        assert_eq!(Ok(1), pool.submit(|| 1).unwrap().join());
+    }
+
+    #[test]
+    fn thread_pool_submit_join_timeout_and_poll() {
+        let pool = ThreadPool::new().unwrap();
+        let (release_sender, release) = mpsc::channel::<()>();
+
+        let handle = pool.submit(move || {
+            release.recv().unwrap();
+            "done"
+        }).unwrap();
+
+        assert_eq!(Err(JoinError::Pending), handle.try_join());
+        assert_eq!(Err(JoinError::Timeout), handle.join_timeout(Duration::from_millis(10)));
+        assert!(!handle.is_finished());
+
+        release_sender.send(()).unwrap();
+
+        assert_eq!(Ok("done"), handle.join_timeout(Duration::from_secs(5)));
+        assert_eq!(Err(JoinError::Taken), handle.try_join());
+        assert_eq!(Err(JoinError::Taken), handle.join_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn thread_pool_submit_abandoned_job_is_cancelled() {
+        let mut pool = ThreadPool::new().unwrap();
+        let (started_sender, started) = mpsc::channel();
+
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }).unwrap();
+        started.recv().unwrap();
+        let handle = pool.submit(|| 1).unwrap();
+
+        pool.shutdown_with(ShutdownPolicy::Abandon, Duration::from_secs(5));
+
+        assert_eq!(Err(JoinError::Cancelled), handle.join());
+    }
+
+    #[test]
+    fn thread_pool_contains_job_panic() {
+        let pool = ThreadPool::new().unwrap();
+
+        pool.execute(|| panic!("bad request")).unwrap();
+        let handle = pool.submit(|| -> i32 { panic!("bad submit") }).unwrap();
+
+        assert!(handle.join().is_err());
+        assert_eq!(Ok(2), pool.submit(|| 2).unwrap().join());
+        assert_eq!(
+            vec![
+                JobPanic { job_id: 0, worker_id: 0, message: String::from("bad request") },
+                JobPanic { job_id: 1, worker_id: 0, message: String::from("bad submit") },
+            ],
+            pool.panics()
+        );
+    }
+
+    #[test]
+    fn thread_pool_respawns_dead_worker() {
+        struct PanicOnDrop;
+
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                panic!("payload dropped");
+            }
+        }
+
+        let mut pool = ThreadPool::new().unwrap();
+
+        // The payload panics again when the worker drops it, outside of the job's containment.
+        pool.execute(|| std::panic::panic_any(PanicOnDrop)).unwrap();
+
+        assert_eq!(Ok(1), pool.submit(|| 1).unwrap().join());
+        assert_eq!(1, pool.shared.lock_workers().len());
+
+        let report = pool.shutdown(Duration::from_secs(5));
+
+        assert!(report.is_finished());
+        assert_eq!(1, report.completed());
+    }
+
+    /// Builds a single worker pool with a queue of `capacity` whose worker is blocked
+    /// until the returned sender is used or dropped.
+    fn blocked_pool(capacity: usize) -> (ThreadPool, mpsc::Sender<()>) {
+        blocked_pool_with(ThreadPool::builder().queue_capacity(capacity))
+    }
+
+    fn blocked_pool_with(builder: ThreadPoolBuilder) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = builder.build().unwrap();
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel();
+
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = release.recv();
+        }).unwrap();
+        started.recv().unwrap();
+
+        (pool, release_sender)
+    }
+
+    #[test]
+    fn thread_pool_builder_rejects_zero_capacity() {
+        let result = ThreadPool::builder().size(2).queue_capacity(0).build();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn thread_pool_try_execute_returns_job_when_full() {
+        // given
+        let m = Arc::new(Mutex::new(0));
+        let (pool, release) = blocked_pool(1);
+        pool.execute(|| {}).unwrap();
+        let to_execute = Arc::clone(&m);
+
+        // when
+        let result = pool.try_execute(move || *to_execute.lock().unwrap() += 1);
+
+        // then
+        let job = match result {
+            Err(TryExecuteError::Full(job)) => job,
+            other => panic!("expected Full, got {:?}", other),
+        };
+        job();
+        assert_eq!(1, *m.lock().unwrap().deref());
+
+        drop(release);
+        let to_execute = Arc::clone(&m);
+        assert!(pool.execute_timeout(move || *to_execute.lock().unwrap() += 1, Duration::from_secs(5)).is_ok());
+        drop(pool);
+        assert_eq!(2, *m.lock().unwrap().deref());
+    }
+
+    #[test]
+    fn thread_pool_execute_timeout_when_full() {
+        let (pool, _release) = blocked_pool(1);
+        pool.execute(|| {}).unwrap();
+
+        let result = pool.execute_timeout(|| {}, Duration::from_millis(10));
+
+        assert!(matches!(result, Err(TryExecuteError::Timeout(_))));
+    }
+
+    #[test]
+    fn thread_pool_execute_blocks_until_room() {
+        let (pool, release) = blocked_pool(1);
+        let pool = Arc::new(pool);
+        pool.execute(|| {}).unwrap();
+
+        let producer = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || pool.submit(|| 3).unwrap().join())
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert!(!producer.is_finished());
+
+        drop(release);
+
+        assert_eq!(Ok(3), producer.join().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_try_execute_after_shutdown() {
+        let mut pool = ThreadPool::new().unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        let result = pool.try_execute(|| {});
+
+        assert!(matches!(result, Err(TryExecuteError::ShutDown(_))));
+    }
+
+    fn saturated_pool(policy: RejectionPolicy) -> (ThreadPool, mpsc::Sender<()>, Arc<Mutex<Vec<i32>>>) {
+        let builder = ThreadPool::builder().queue_capacity(2).rejection_policy(policy);
+        let (pool, release) = blocked_pool_with(builder);
+        let ran = Arc::new(Mutex::new(Vec::new()));
+
+        for i in 0..3 {
+            let ran = Arc::clone(&ran);
+            let result = pool.execute(move || ran.lock().unwrap().push(i));
+            assert_eq!(i == 2 && policy == RejectionPolicy::Abort, matches!(result, Err(PoolError::QueueFull)));
+        }
+
+        (pool, release, ran)
+    }
+
+    #[test]
+    fn thread_pool_rejection_abort() {
+        let (pool, release, ran) = saturated_pool(RejectionPolicy::Abort);
+        drop(release);
+        let aborted = pool.rejections().aborted;
+        drop(pool);
+
+        assert_eq!(1, aborted);
+        assert_eq!(vec![0, 1], *ran.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_rejection_caller_runs() {
+        let (pool, release, ran) = saturated_pool(RejectionPolicy::CallerRuns);
+
+        assert_eq!(vec![2], *ran.lock().unwrap());
+        drop(release);
+        assert_eq!(1, pool.rejections().caller_runs);
+        drop(pool);
+        assert_eq!(vec![2, 0, 1], *ran.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_rejection_discard_newest() {
+        let (pool, release, ran) = saturated_pool(RejectionPolicy::DiscardNewest);
+
+        drop(release);
+        assert_eq!(RejectionStats { discarded_newest: 1, ..Default::default() }, pool.rejections());
+        drop(pool);
+        assert_eq!(vec![0, 1], *ran.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_rejection_discard_oldest() {
+        let (pool, release, ran) = saturated_pool(RejectionPolicy::DiscardOldest);
+
+        drop(release);
+        assert_eq!(RejectionStats { discarded_oldest: 1, ..Default::default() }, pool.rejections());
+        drop(pool);
+        assert_eq!(vec![1, 2], *ran.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_builder_rejects_max_below_size() {
+        let result = ThreadPool::builder().size(4).max_size(2).build();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn thread_pool_elastic_grows_and_retires() {
+        // given
+        let pool = ThreadPool::builder()
+            .size(1)
+            .max_size(3)
+            .keep_alive(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel::<()>();
+        let release = Arc::new(Mutex::new(release));
+
+        // when
+        for _ in 0..5 {
+            let started_sender = started_sender.clone();
+            let release = Arc::clone(&release);
+            pool.execute(move || {
+                started_sender.send(()).unwrap();
+                let _ = release.lock().unwrap().recv();
+            }).unwrap();
+        }
+
+        // then
+        for _ in 0..3 {
+            started.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(3, pool.size());
+
+        drop(release_sender);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.size() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(1, pool.size());
+    }
+
+    #[test]
+    fn thread_pool_shutdown_report_counts_jobs_of_retired_workers() {
+        // given
+        let mut pool = ThreadPool::builder()
+            .size(1)
+            .max_size(4)
+            .keep_alive(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel::<()>();
+        let release = Arc::new(Mutex::new(release));
+        for _ in 0..4 {
+            let started_sender = started_sender.clone();
+            let release = Arc::clone(&release);
+            pool.execute(move || {
+                started_sender.send(()).unwrap();
+                let _ = release.lock().unwrap().recv();
+            }).unwrap();
+        }
+        for _ in 0..4 {
+            started.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        for _ in 0..36 {
+            pool.execute(|| {}).unwrap();
+        }
+
+        // when
+        drop(release_sender);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().retired.workers < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let stats = pool.stats();
+        let report = pool.shutdown(Duration::from_secs(5));
+
+        // then
+        assert_eq!(3, stats.retired.workers);
+        assert_eq!(40, stats.completed);
+        let per_worker: usize = stats.per_worker.iter().map(|worker| worker.completed).sum();
+        assert_eq!(40, stats.retired.completed + per_worker);
+        assert_eq!(40, report.completed());
+        assert_eq!(3, report.retired.workers);
+    }
+
+    #[test]
+    fn thread_pool_steals_jobs_submitted_by_busy_worker() {
+        let pool = Arc::new(ThreadPool::build(2).unwrap());
+        let inner_pool = Arc::clone(&pool);
+
+        let outer = pool.submit(move || {
+            let outer_thread = thread::current().id();
+            // The inner job lands on this worker's local deque while this worker stays busy,
+            // so it can only run if the other worker steals it.
+            let inner = inner_pool.submit(|| thread::current().id()).unwrap();
+            let inner_thread = inner.join_timeout(Duration::from_secs(5));
+            drop(inner_pool);
+            (outer_thread, inner_thread)
+        }).unwrap();
+
+        let (outer_thread, inner_thread) = outer.join().unwrap();
+        assert_ne!(Ok(outer_thread), inner_thread);
+    }
+
+    #[test]
+    fn thread_pool_dropped_by_its_own_job_does_not_wait_for_it() {
+        // given
+        let pool = Arc::new(ThreadPool::build(2).unwrap());
+        let (owned_sender, owned) = mpsc::channel::<()>();
+        let (dropped_sender, dropped) = mpsc::channel();
+        let last_owner = Arc::clone(&pool);
+        pool.execute(move || {
+            let _ = owned.recv();
+            drop(last_owner);
+            dropped_sender.send(()).unwrap();
+        }).unwrap();
+
+        // when
+        drop(pool);
+        drop(owned_sender);
+
+        // then
+        assert_eq!(Ok(()), dropped.recv_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn thread_pool_runs_every_nested_job() {
+        let pool = Arc::new(ThreadPool::build(4).unwrap());
+        let (done_sender, done) = mpsc::channel();
+
+        for _ in 0..50 {
+            let inner_pool = Arc::clone(&pool);
+            let done_sender = done_sender.clone();
+            pool.execute(move || {
+                for _ in 0..20 {
+                    let done_sender = done_sender.clone();
+                    inner_pool.execute(move || done_sender.send(()).unwrap()).unwrap();
+                }
+            }).unwrap();
+        }
+
+        for _ in 0..1000 {
+            done.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn thread_pool_runs_higher_priority_first() {
+        // given
+        let (pool, release) = blocked_pool_with(ThreadPool::builder());
+        let ran = Arc::new(Mutex::new(Vec::new()));
+
+        for (i, priority) in [Priority::Low, Priority::Normal, Priority::High, Priority::Normal].into_iter().enumerate() {
+            let ran = Arc::clone(&ran);
+            pool.execute_with_priority(priority, move || ran.lock().unwrap().push(i)).unwrap();
+        }
+        assert_eq!(QueueLengths { high: 1, normal: 2, low: 1 }, pool.queue_lengths());
+
+        // when
+        drop(release);
+        drop(pool);
+
+        // then
+        assert_eq!(vec![2, 1, 3, 0], *ran.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_does_not_starve_low_priority() {
+        // given
+        let (pool, release) = blocked_pool_with(ThreadPool::builder());
+        let ran = Arc::new(Mutex::new(Vec::new()));
+
+        let to_execute = Arc::clone(&ran);
+        pool.execute_with_priority(Priority::Low, move || to_execute.lock().unwrap().push(Priority::Low)).unwrap();
+        for _ in 0..20 {
+            let ran = Arc::clone(&ran);
+            pool.execute_with_priority(Priority::High, move || ran.lock().unwrap().push(Priority::High)).unwrap();
+        }
+
+        // when
+        drop(release);
+        drop(pool);
+
+        // then
+        let ran = ran.lock().unwrap();
+        assert_eq!(21, ran.len());
+        assert_eq!(Some(8), ran.iter().position(|priority| *priority == Priority::Low));
+    }
+
+    #[test]
+    fn thread_pool_execute_after_waits_for_delay() {
+        let pool = ThreadPool::new().unwrap();
+        let (sender, ran) = mpsc::channel();
+        let start = Instant::now();
+
+        pool.execute_after(Duration::from_millis(50), move || sender.send(Instant::now()).unwrap()).unwrap();
+
+        let at = ran.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(at - start >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn thread_pool_execute_at_runs_in_deadline_order() {
+        let pool = ThreadPool::new().unwrap();
+        let (sender, ran) = mpsc::channel();
+        let now = Instant::now();
+
+        for (i, delay) in [30, 10, 20].into_iter().enumerate() {
+            let sender = sender.clone();
+            pool.execute_at(now + Duration::from_millis(delay), move || sender.send(i).unwrap()).unwrap();
+        }
+
+        let order: Vec<_> = (0..3).map(|_| ran.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        assert_eq!(vec![1, 2, 0], order);
+    }
+
+    #[test]
+    fn thread_pool_cancelled_scheduled_job_does_not_run() {
+        let mut pool = ThreadPool::new().unwrap();
+        let ran = Arc::new(AtomicBool::new(false));
+
+        let to_execute = Arc::clone(&ran);
+        let handle = pool.execute_after(Duration::from_millis(20), move || to_execute.store(true, Ordering::SeqCst)).unwrap();
+        handle.cancel();
+        thread::sleep(Duration::from_millis(50));
+        pool.shutdown(Duration::from_secs(5));
+
+        assert!(handle.is_cancelled());
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn thread_pool_fixed_rate_repeats_until_cancelled() {
+        // given
+        let pool = ThreadPool::new().unwrap();
+        let (sender, ran) = mpsc::channel();
+
+        // when
+        let handle = pool.execute_at_fixed_rate(Duration::from_millis(10), move || { let _ = sender.send(()); }).unwrap();
+        for _ in 0..3 {
+            ran.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        handle.cancel();
+
+        // then
+        // A run that was already queued may still finish; nothing runs after that.
+        while ran.recv_timeout(Duration::from_millis(50)).is_ok() {}
+        assert!(handle.is_cancelled());
+    }
+
+    #[test]
+    fn thread_pool_fixed_rate_is_cancelled_after_panicking_run() {
+        // given
+        let pool = ThreadPool::new().unwrap();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+
+        // when
+        let handle = pool.execute_at_fixed_rate(Duration::from_millis(10), move || {
+            if counter.fetch_add(1, Ordering::SeqCst) == 1 {
+                panic!("second run failed");
+            }
+        }).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().panicked == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(50));
+
+        // then
+        assert!(handle.is_cancelled());
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn thread_pool_shutdown_cancels_pending_scheduled_jobs() {
+        let mut pool = ThreadPool::new().unwrap();
+
+        let once = pool.execute_after(Duration::from_secs(60), || {}).unwrap();
+        let repeating = pool.execute_at_fixed_rate(Duration::from_secs(60), || {}).unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        assert!(once.is_cancelled());
+        assert!(repeating.is_cancelled());
+        assert!(matches!(pool.execute_after(Duration::ZERO, || {}), Err(PoolError::ShutDown)));
+    }
+
+    #[test]
+    fn thread_pool_stats_counts_jobs() {
+        // given
+        let mut pool = ThreadPool::build(2).unwrap();
+
+        // when
+        for _ in 0..4 {
+            pool.execute(|| {}).unwrap();
+        }
+        pool.execute(|| panic!("job failed")).unwrap();
+        pool.execute(|| thread::sleep(Duration::from_millis(20))).unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        // then
+        let stats = pool.stats();
+        assert_eq!(6, stats.submitted);
+        assert_eq!(5, stats.completed);
+        assert_eq!(1, stats.panicked);
+        assert_eq!(0, stats.queued.total());
+        assert_eq!(6, stats.queue_wait.count());
+        assert_eq!(6, stats.execution.count());
+        assert!(stats.execution.max() >= Duration::from_millis(20));
+        assert!(stats.execution.percentile(1.0) >= Duration::from_millis(20));
+        assert!(stats.execution.percentile(0.5) < Duration::from_millis(20));
+        assert_eq!(6, stats.execution.buckets().map(|(_, count)| count).sum::<u64>());
+    }
+
+    #[test]
+    fn thread_pool_stats_reports_active_workers() {
+        // given
+        let (pool, release) = blocked_pool_with(ThreadPool::builder());
+        pool.execute(|| {}).unwrap();
+
+        // when
+        let stats = pool.stats();
+        drop(release);
+
+        // then
+        assert_eq!(1, stats.workers);
+        assert_eq!(1, stats.active_workers);
+        assert!(stats.per_worker[0].active);
+        assert_eq!(1, stats.queued.normal);
+        assert_eq!(2, stats.submitted);
+    }
+
+    #[test]
+    fn thread_pool_stats_tracks_busy_time_per_worker() {
+        let pool = ThreadPool::new().unwrap();
+        let (sender, done) = mpsc::channel();
+
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send(()).unwrap();
+        }).unwrap();
+        done.recv().unwrap();
+        while pool.stats().completed == 0 {
+            thread::yield_now();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(1, stats.per_worker.len());
+        assert_eq!(1, stats.per_worker[0].completed);
+        assert!(stats.per_worker[0].busy >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn thread_pool_builder_names_threads() {
+        let pool = ThreadPool::builder().size(2).thread_name_prefix("http").stack_size(256 * 1024).build().unwrap();
+
+        let name = pool.submit(|| thread::current().name().map(String::from)).unwrap().join().unwrap().unwrap();
+
+        assert!(name == "http-0" || name == "http-1", "unexpected name {name}");
+    }
+
+    #[test]
+    fn thread_pool_builder_runs_lifecycle_hooks() {
+        // given
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        let on_start = Arc::clone(&started);
+        let on_stop = Arc::clone(&stopped);
+
+        // when
+        let mut pool = ThreadPool::builder()
+            .size(2)
+            .on_thread_start(move |id| on_start.lock().unwrap().push(id))
+            .on_thread_stop(move |id| on_stop.lock().unwrap().push(id))
+            .build()
+            .unwrap();
+        pool.execute(|| {}).unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        // then
+        started.lock().unwrap().sort();
+        stopped.lock().unwrap().sort();
+        assert_eq!(vec![0, 1], *started.lock().unwrap());
+        assert_eq!(vec![0, 1], *stopped.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_builder_fails_if_start_hook_panics() {
+        // when
+        let result = ThreadPool::builder()
+            .size(2)
+            .on_thread_start(|id| if id == 1 { panic!("no config for worker {id}") })
+            .build();
+
+        // then
+        assert!(matches!(result, Err(PoolError::ThreadStart(message)) if message == "no config for worker 1"));
+    }
+
+    #[test]
+    fn thread_pool_does_not_respawn_worker_whose_start_hook_panicked() {
+        // given
+        let starts = Arc::new(AtomicUsize::new(0));
+        let on_start = Arc::clone(&starts);
+        let pool = ThreadPool::builder()
+            .size(1)
+            .max_size(2)
+            .on_thread_start(move |id| if id == 1 {
+                on_start.fetch_add(1, Ordering::SeqCst);
+                panic!("worker {id} cannot start");
+            })
+            .build()
+            .unwrap();
+        let (release_sender, release) = mpsc::channel::<()>();
+        pool.execute(move || { let _ = release.recv(); }).unwrap();
+
+        // when
+        pool.execute(|| {}).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        release_sender.send(()).unwrap();
+
+        // then
+        assert_eq!(1, starts.load(Ordering::SeqCst));
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn thread_pool_builder_reports_invalid_config() {
+        assert!(matches!(ThreadPool::build(0), Err(PoolError::ZeroSize)));
+        assert!(matches!(
+            ThreadPool::builder().size(4).max_size(2).build(),
+            Err(PoolError::MaxSizeBelowSize { size: 4, max_size: 2 })
+        ));
+        assert_eq!("pool size has to be greater than 0", PoolError::ZeroSize.to_string());
+    }
+
+    #[test]
+    fn thread_pool_fixed_rate_rejects_zero_period() {
+        let pool = ThreadPool::new().unwrap();
+
+        assert!(matches!(pool.execute_at_fixed_rate(Duration::ZERO, || {}), Err(PoolError::ZeroPeriod)));
+    }
+
+    #[test]
+    fn cancellation_token_cancels_children() {
+        let group = CancellationToken::new();
+        let first = group.child_token();
+        let second = group.child_token();
+        let grandchild = first.child_token();
+
+        second.cancel();
+        assert!(!group.is_cancelled());
+        assert!(!first.is_cancelled());
+
+        group.cancel();
+        assert!(first.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        assert!(group.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn cancellation_token_wakes_waiter() {
+        let token = CancellationToken::new();
+        let to_cancel = token.clone();
+        let start = Instant::now();
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            to_cancel.cancel();
+        });
+
+        assert!(token.wait_timeout(Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!CancellationToken::new().wait_timeout(Duration::from_millis(1)));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn thread_pool_skips_job_cancelled_before_start() {
+        // given
+        let (pool, release) = blocked_pool_with(ThreadPool::builder());
+        let ran = Arc::new(AtomicBool::new(false));
+        let token = CancellationToken::new();
+
+        let to_execute = Arc::clone(&ran);
+        pool.execute_with_token(&token, move |_| to_execute.store(true, Ordering::SeqCst)).unwrap();
+
+        // when
+        token.cancel();
+        drop(release);
+        drop(pool);
+
+        // then
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn thread_pool_shutdown_cancels_running_jobs() {
+        // given
+        let mut pool = ThreadPool::build(2).unwrap();
+        let (started_sender, started) = mpsc::channel();
+        let pool_token = pool.cancellation_token();
+
+        let first_started = started_sender.clone();
+        let first = pool.submit(move || {
+            first_started.send(()).unwrap();
+            pool_token.wait_timeout(Duration::from_secs(60))
+        }).unwrap();
+        let (sender, second) = mpsc::channel();
+        pool.execute_with_token(&CancellationToken::new(), move |token| {
+            started_sender.send(()).unwrap();
+            sender.send(token.wait_timeout(Duration::from_secs(60))).unwrap();
+        }).unwrap();
+        started.recv().unwrap();
+        started.recv().unwrap();
+
+        // when
+        let report = pool.shutdown(Duration::from_secs(5));
+
+        // then
+        assert!(report.is_finished());
+        assert!(pool.cancellation_token().is_cancelled());
+        assert_eq!(Ok(true), first.join());
+        assert_eq!(Ok(true), second.recv());
+    }
+
+    #[test]
+    fn thread_pool_scope_borrows_from_stack() {
+        let pool = ThreadPool::build(3).unwrap();
+        let words = ["a", "bb", "ccc"];
+        let mut lengths = [0; 3];
+
+        pool.scope(|s| {
+            for (word, length) in words.iter().zip(lengths.iter_mut()) {
+                s.execute(move || *length = word.len());
+            }
+        });
+
+        assert_eq!([1, 2, 3], lengths);
+    }
+
+    #[test]
+    fn thread_pool_scope_runs_nested_jobs_inside_worker() {
+        let pool = Arc::new(ThreadPool::new().unwrap());
+        let inner = Arc::clone(&pool);
+
+        let total = pool.submit(move || {
+            let counter = AtomicUsize::new(0);
+            inner.scope(|s| {
+                for _ in 0..4 {
+                    s.execute(|| {
+                        s.execute(|| { counter.fetch_add(1, Ordering::SeqCst); });
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+            drop(inner);
+            counter.into_inner()
+        }).unwrap();
+
+        assert_eq!(Ok(8), total.join_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn thread_pool_scope_propagates_panic_after_jobs_finish() {
+        let pool = ThreadPool::build(2).unwrap();
+        let finished = AtomicBool::new(false);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.execute(|| panic!("scoped job failed"));
+                s.execute(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    finished.store(true, Ordering::SeqCst);
+                });
+            })
+        }));
+
+        let payload = result.unwrap_err();
+        assert_eq!(Some(&"scoped job failed"), payload.downcast_ref::<&str>());
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn thread_pool_scope_runs_jobs_after_shutdown() {
+        let mut pool = ThreadPool::new().unwrap();
+        pool.shutdown(Duration::from_secs(5));
+        let mut ran = false;
+
+        pool.scope(|s| s.execute(|| ran = true));
+
+        assert!(ran);
+    }
+
+    #[test]
+    fn thread_pool_map_keeps_order() {
+        let pool = ThreadPool::build(3).unwrap();
+
+        let squares = pool.map(0..100, |n| n * n);
+
+        assert_eq!((0..100).map(|n| n * n).collect::<Vec<_>>(), squares);
+        assert!(pool.map(Vec::<i32>::new(), |n| n).is_empty());
+    }
+
+    #[test]
+    fn thread_pool_for_each_visits_every_item() {
+        let pool = ThreadPool::build(2).unwrap();
+        let sum = AtomicUsize::new(0);
+
+        pool.for_each(1..=10, |n| { sum.fetch_add(n, Ordering::SeqCst); });
+
+        assert_eq!(55, sum.into_inner());
+    }
+
+    #[test]
+    fn thread_pool_reduce_combines_in_order() {
+        let pool = ThreadPool::build(2).unwrap();
+        let words = (0..50).map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let joined = pool.reduce(words.clone(), String::new, |a, b| a + &b);
+
+        assert_eq!(words.concat(), joined);
+        assert_eq!(0, pool.reduce(Vec::new(), || 0, |a, b| a + b));
+    }
+
+    type LoggedRecord = (Level, Option<usize>, Option<u64>, String);
+
+    #[derive(Default)]
+    struct CollectingLogger {
+        records: Mutex<Vec<LoggedRecord>>,
+    }
+
+    impl Logger for Arc<CollectingLogger> {
+        fn enabled(&self, level: Level) -> bool {
+            level <= Level::Debug
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            let entry = (record.level, record.worker_id, record.job_id, record.message.to_string());
+            self.records.lock().unwrap().push(entry);
+        }
+    }
+
+    #[test]
+    fn thread_pool_logs_through_logger() {
+        // given
+        let logger = Arc::new(CollectingLogger::default());
+        let mut pool = ThreadPool::builder().logger(Arc::clone(&logger)).build().unwrap();
+
+        // when
+        pool.execute(|| {}).unwrap();
+        pool.execute(|| panic!("job failed")).unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        // then
+        let records = logger.records.lock().unwrap();
+        assert!(records.iter().all(|(level, ..)| *level <= Level::Debug));
+        assert!(records.contains(&(Level::Error, Some(0), Some(1), String::from("Job panicked: job failed"))));
+        assert!(records.contains(&(Level::Debug, Some(0), None, String::from("Shutting down worker"))));
+    }
+
+    #[test]
+    fn thread_pool_watchdog_reports_slow_job() {
+        let logger = Arc::new(CollectingLogger::default());
+        let mut pool = ThreadPool::builder().soft_time_limit(Duration::from_millis(20)).logger(Arc::clone(&logger)).build().unwrap();
+
+        pool.execute(|| thread::sleep(Duration::from_millis(100))).unwrap();
+        pool.execute(|| {}).unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        assert_eq!(1, pool.stats().slow_jobs);
+        let records = logger.records.lock().unwrap();
+        assert_eq!(1, records.iter().filter(|(level, ..)| *level == Level::Warn).count());
+    }
+
+    #[test]
+    fn thread_pool_watchdog_replaces_stuck_worker() {
+        // given
+        let (mut pool, release) = blocked_pool_with(ThreadPool::builder().hard_time_limit(Duration::from_millis(20)));
+
+        // when
+        let handle = pool.submit(|| 42).unwrap();
+
+        // then
+        assert_eq!(Ok(42), handle.join_timeout(Duration::from_secs(5)));
+        let stats = pool.stats();
+        assert_eq!(1, stats.lost_workers);
+        assert_eq!(1, pool.size());
+        assert_eq!(1, stats.per_worker[0].id);
+        assert_eq!(1, stats.lost.workers);
+
+        // when
+        drop(release);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().lost.completed == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        // then
+        let stats = pool.stats();
+        let per_worker: usize = stats.per_worker.iter().map(|worker| worker.completed).sum();
+        assert_eq!(1, stats.lost.completed);
+        assert_eq!(2, stats.completed);
+        assert_eq!(stats.completed as usize, per_worker + stats.retired.completed + stats.lost.completed);
+        assert_eq!(2, pool.shutdown(Duration::from_secs(5)).completed());
+    }
+
+    #[test]
+    fn thread_pool_builder_rejects_zero_time_limit() {
+        assert!(matches!(ThreadPool::builder().hard_time_limit(Duration::ZERO).build(), Err(PoolError::ZeroTimeLimit)));
+    }
+
+    #[test]
+    fn state_pool_builds_state_once_per_thread() {
+        // given
+        let created = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&created);
+        let mut pool = ThreadPool::build_with_state(2, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Vec::<usize>::new()
+        }).unwrap();
+
+        // when
+        let handles: Vec<_> = (0..20)
+            .map(|i| pool.submit(move |seen: &mut Vec<usize>| {
+                seen.push(i);
+                seen.len()
+            }).unwrap())
+            .collect();
+        let lengths: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        pool.shutdown(Duration::from_secs(5));
+
+        // then
+        let created = created.load(Ordering::SeqCst);
+        assert!((1..=2).contains(&created), "state created {created} times");
+        assert_eq!(20, lengths.len());
+        assert!(lengths.iter().any(|length| *length > 1));
+    }
+
+    #[test]
+    fn state_pool_rebuilds_state_after_panic() {
+        let pool = ThreadPool::build_with_state(1, || 0).unwrap();
+
+        pool.execute(|count: &mut i32| *count += 1).unwrap();
+        pool.execute(|_: &mut i32| panic!("job failed")).unwrap();
+        let after_panic = pool.submit(|count: &mut i32| *count).unwrap();
+
+        assert_eq!(Ok(0), after_panic.join());
+        assert_eq!(1, pool.stats().panicked);
+    }
+
+    #[test]
+    fn state_pool_keeps_outer_state_after_reentrant_job() {
+        // given
+        let pool = Arc::new(ThreadPool::builder()
+            .size(1)
+            .queue_capacity(1)
+            .rejection_policy(RejectionPolicy::CallerRuns)
+            .build_with_state(|| 0)
+            .unwrap());
+        let inner_pool = Arc::clone(&pool);
+
+        // when
+        pool.execute(move |count: &mut i32| {
+            *count += 10;
+            inner_pool.execute(|count: &mut i32| *count += 1).unwrap();
+            // The queue is full, so this one runs right here with a fresh state.
+            inner_pool.execute(|count: &mut i32| *count += 100).unwrap();
+        }).unwrap();
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        let count = pool.submit(|count: &mut i32| *count).unwrap();
+
+        // then
+        assert_eq!(Ok(11), count.join_timeout(Duration::from_secs(5)));
+        assert_eq!(1, pool.rejections().caller_runs);
+    }
+
+    #[test]
+    fn state_pool_drops_caller_states_with_pool() {
+        struct Tracked(Arc<AtomicUsize>);
+
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        // given
+        let created = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let (on_create, on_drop) = (Arc::clone(&created), Arc::clone(&dropped));
+        let pool = ThreadPool::builder()
+            .size(1)
+            .queue_capacity(1)
+            .rejection_policy(RejectionPolicy::CallerRuns)
+            .build_with_state(move || {
+                on_create.fetch_add(1, Ordering::SeqCst);
+                Tracked(Arc::clone(&on_drop))
+            })
+            .unwrap();
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel::<()>();
+        pool.execute(move |_: &mut Tracked| {
+            started_sender.send(()).unwrap();
+            let _ = release.recv();
+        }).unwrap();
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        pool.execute(|_: &mut Tracked| {}).unwrap();
+        pool.execute(|_: &mut Tracked| {}).unwrap();
+        assert_eq!(1, pool.rejections().caller_runs);
+
+        // when
+        drop(release_sender);
+        drop(pool);
+
+        // then
+        assert_eq!(created.load(Ordering::SeqCst), dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn affinity_assigns_cores_round_robin() {
+        let available = [0, 1, 2, 3];
+
+        assert_eq!(None, Affinity::None.cores_for(0, &available));
+        assert_eq!(Some(vec![1]), Affinity::OnePerCore.cores_for(5, &available));
+        assert_eq!(Some(vec![3]), Affinity::Cores(vec![2, 3, 9]).cores_for(1, &available));
+        assert_eq!(Some(vec![0, 2]), Affinity::CoreSet(vec![0, 2, 9]).cores_for(7, &available));
+        assert_eq!(None, Affinity::Cores(vec![8, 9]).cores_for(0, &available));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn thread_pool_pins_workers() {
+        let pool = ThreadPool::builder().size(2).affinity(Affinity::OnePerCore).build().unwrap();
+        let available = affinity::available().unwrap();
+
+        // Workers pin themselves when their thread starts.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().per_worker.iter().any(|worker| worker.cpus.is_none()) && Instant::now() < deadline {
+            thread::yield_now();
+        }
+        let stats = pool.stats();
+
+        assert_eq!(Affinity::OnePerCore, stats.affinity);
+        for worker in stats.per_worker {
+            let cpus = worker.cpus.unwrap();
+            assert_eq!(1, cpus.len());
+            assert!(available.contains(&cpus[0]));
+        }
+    }
+
+    #[test]
+    fn thread_pool_runs_unpinned_when_cores_unavailable() {
+        let pool = ThreadPool::builder().affinity(Affinity::Cores(vec![usize::MAX])).build().unwrap();
+
+        assert_eq!(Ok(1), pool.submit(|| 1).unwrap().join());
+        assert_eq!(None, pool.stats().per_worker[0].cpus);
+    }
+
+    #[test]
+    fn thread_pool_wait_idle_keeps_pool_running() {
+        // given
+        let pool = ThreadPool::build(2).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        for round in 1..=2 {
+            // when
+            for _ in 0..10 {
+                let count = Arc::clone(&count);
+                pool.execute(move || {
+                    thread::sleep(Duration::from_millis(1));
+                    count.fetch_add(1, Ordering::SeqCst);
+                }).unwrap();
+            }
+            pool.wait_idle();
+
+            // then
+            assert_eq!(round * 10, count.load(Ordering::SeqCst));
+            assert_eq!(round as u64 * 10, pool.stats().completed);
+        }
+    }
+
+    #[test]
+    fn thread_pool_wait_idle_timeout() {
+        let (pool, release) = blocked_pool_with(ThreadPool::builder());
+        pool.execute(|| {}).unwrap();
+
+        assert!(!pool.wait_idle_timeout(Duration::from_millis(20)));
+        drop(release);
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        assert_eq!(0, pool.stats().active_workers);
+    }
+
+    #[test]
+    fn thread_pool_wait_idle_counts_discarded_jobs() {
+        let (pool, release, _) = saturated_pool(RejectionPolicy::DiscardOldest);
+        drop(release);
+
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        assert_eq!(1, pool.stats().discarded);
+    }
+
+    #[test]
+    fn thread_pool_pause_keeps_jobs_queued_until_resume() {
+        // given
+        let pool = ThreadPool::build(2).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        pool.pause();
+
+        // when
+        for _ in 0..5 {
+            let count = Arc::clone(&count);
+            pool.execute(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            }).unwrap();
+        }
+
+        // then
+        assert!(!pool.wait_idle_timeout(Duration::from_millis(50)));
+        assert_eq!(0, count.load(Ordering::SeqCst));
+        let stats = pool.stats();
+        assert!(stats.paused);
+        assert_eq!(5, stats.queued.normal);
+
+        // when
+        pool.resume();
+
+        // then
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        assert_eq!(5, count.load(Ordering::SeqCst));
+        assert!(!pool.stats().paused);
+    }
+
+    #[test]
+    fn thread_pool_shutdown_drains_paused_pool() {
+        let mut pool = ThreadPool::build(2).unwrap();
+        pool.pause();
+        for _ in 0..3 {
+            pool.execute(|| {}).unwrap();
+        }
+
+        let report = pool.shutdown(Duration::from_secs(5));
+
+        assert!(report.is_finished());
+        assert_eq!(3, report.completed());
+        assert!(!pool.is_paused());
+    }
+
+    #[test]
+    fn thread_pool_execute_keyed_runs_each_key_in_order() {
+        // given
+        let pool = ThreadPool::build(4).unwrap();
+        let keys: Vec<_> = (0..3).map(|_| (Arc::new(AtomicBool::new(false)), Arc::new(Mutex::new(Vec::new())))).collect();
+
+        // when
+        for i in 0..20 {
+            for (key, (running, order)) in keys.iter().enumerate() {
+                let running = Arc::clone(running);
+                let order = Arc::clone(order);
+                pool.execute_keyed(key, move || {
+                    assert!(!running.swap(true, Ordering::SeqCst));
+                    thread::sleep(Duration::from_micros(100));
+                    order.lock().unwrap().push(i);
+                    running.store(false, Ordering::SeqCst);
+                }).unwrap();
+            }
+        }
+        pool.wait_idle();
+
+        // then
+        for (_, order) in &keys {
+            assert_eq!((0..20).collect::<Vec<_>>(), *order.lock().unwrap());
+        }
+        assert_eq!(0, pool.stats().panicked);
+    }
+
+    #[test]
+    fn thread_pool_execute_keyed_runs_other_keys_in_parallel() {
+        // given
+        let pool = ThreadPool::build(2).unwrap();
+        let (release_sender, release) = mpsc::channel::<()>();
+        let (done_sender, done) = mpsc::channel();
+        pool.execute_keyed("upload-1", move || {
+            let _ = release.recv();
+        }).unwrap();
+
+        // when
+        let first = done_sender.clone();
+        pool.execute_keyed("upload-1", move || first.send(1).unwrap()).unwrap();
+        pool.execute_keyed("upload-2", move || done_sender.send(2).unwrap()).unwrap();
+
+        // then
+        assert_eq!(Ok(2), done.recv_timeout(Duration::from_secs(5)));
+        assert!(done.recv_timeout(Duration::from_millis(20)).is_err());
+        drop(release_sender);
+        assert_eq!(Ok(1), done.recv_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn thread_pool_execute_keyed_continues_after_panic() {
+        let pool = ThreadPool::new().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        pool.execute_keyed(7, || panic!("keyed job failed")).unwrap();
+        let counter = Arc::clone(&count);
+        pool.execute_keyed(7, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }).unwrap();
+
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        assert_eq!(1, count.load(Ordering::SeqCst));
+        assert_eq!(1, pool.stats().panicked);
+    }
+
+    fn run_fair(pool: &ThreadPool, jobs: &[&'static str]) -> Arc<Mutex<Vec<&'static str>>> {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for &key in jobs {
+            let order = Arc::clone(&order);
+            pool.execute_fair(key, move || order.lock().unwrap().push(key)).unwrap();
+        }
+        order
+    }
+
+    #[test]
+    fn thread_pool_execute_fair_round_robins_keys() {
+        // given
+        let (pool, release) = blocked_pool_with(ThreadPool::builder());
+
+        // when
+        let order = run_fair(&pool, &["a", "a", "a", "a", "b", "b"]);
+        drop(release);
+        pool.wait_idle();
+
+        // then
+        assert_eq!(vec!["a", "b", "a", "b", "a", "a"], *order.lock().unwrap());
+    }
+
+    #[test]
+    fn thread_pool_execute_fair_applies_weights() {
+        // given
+        let (pool, release) = blocked_pool_with(ThreadPool::builder());
+        pool.set_fair_weight("a", 2).unwrap();
+
+        // when
+        let order = run_fair(&pool, &["a", "a", "a", "a", "b", "b"]);
+        drop(release);
+        pool.wait_idle();
+
+        // then
+        assert_eq!(vec!["a", "a", "b", "a", "a", "b"], *order.lock().unwrap());
+        assert!(matches!(pool.set_fair_weight("b", 0), Err(PoolError::ZeroWeight)));
+    }
+
+    #[test]
+    fn thread_pool_execute_fair_caps_jobs_per_key() {
+        let (pool, release) = blocked_pool_with(ThreadPool::builder().max_queued_per_key(2));
+
+        pool.execute_fair("a", || {}).unwrap();
+        pool.execute_fair("a", || {}).unwrap();
+
+        assert!(matches!(pool.execute_fair("a", || {}), Err(PoolError::QueueFull)));
+        assert!(pool.execute_fair("b", || {}).is_ok());
+        assert_eq!(2, pool.fair_queue_len("a"));
+        drop(release);
+        pool.wait_idle();
+        assert_eq!(0, pool.fair_queue_len("a"));
+        assert_eq!(4, pool.stats().completed);
+    }
+
+    #[test]
+    fn pool_handle_submits_from_inside_job() {
+        // given
+        let pool = ThreadPool::build(2).unwrap();
+        let handle = pool.handle();
+        let (sender, receiver) = mpsc::channel();
+
+        // when
+        pool.execute(move || {
+            let follow_up = handle.clone();
+            handle.execute(move || {
+                sender.send(follow_up.submit(|| 42).unwrap()).unwrap();
+            }).unwrap();
+        }).unwrap();
+
+        // then
+        let result = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Ok(42), result.join());
+    }
+
+    #[test]
+    fn pool_handle_fails_after_shutdown() {
+        // given
+        let mut pool = ThreadPool::new().unwrap();
+        let handle = pool.handle();
+
+        // when
+        pool.shutdown(Duration::from_secs(5));
+        drop(pool);
+
+        // then
+        assert!(matches!(handle.execute(|| {}), Err(PoolError::ShutDown)));
+        assert!(matches!(handle.submit(|| {}), Err(PoolError::ShutDown)));
+        assert!(matches!(handle.execute_after(Duration::ZERO, || {}), Err(PoolError::ShutDown)));
+        assert!(matches!(handle.execute_keyed(1, || {}), Err(PoolError::ShutDown)));
+        assert!(matches!(handle.execute_fair(1, || {}), Err(PoolError::ShutDown)));
+        assert_eq!(vec![2, 4], handle.map(vec![1, 2], |x| x * 2));
+    }
+
+    /// Future that completes once `open` is called, from any thread.
+    #[derive(Clone, Default)]
+    struct Gate {
+        state: Arc<Mutex<(bool, Option<std::task::Waker>)>>,
+    }
+
+    impl Gate {
+        fn open(&self) {
+            let mut state = self.state.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    impl Future for Gate {
+        type Output = ();
+
+        fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<()> {
+            let mut state = self.state.lock().unwrap();
+            if state.0 {
+                return std::task::Poll::Ready(());
+            }
+            state.1 = Some(cx.waker().clone());
+            std::task::Poll::Pending
+        }
+    }
+
+    #[test]
+    fn thread_pool_spawn_future_resumes_when_woken() {
+        // given
+        let pool = ThreadPool::new().unwrap();
+        let gate = Gate::default();
+        let opened = gate.clone();
+
+        // when
+        let handle = pool.spawn_future(async move {
+            opened.await;
+            42
+        }).unwrap();
+
+        // then
+        assert_eq!(Err(JoinError::Timeout), handle.join_timeout(Duration::from_millis(20)));
+        assert!(pool.submit(|| 1).unwrap().join().is_ok());
+
+        // when
+        gate.open();
+
+        // then
+        assert_eq!(Ok(42), handle.join_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn thread_pool_spawn_future_reports_panic() {
+        let pool = ThreadPool::new().unwrap();
+
+        let handle = pool.spawn_future(async {
+            panic!("future failed");
+        }).unwrap();
+
+        assert_eq!(Err(JoinError::Panicked(String::from("future failed"))), handle.join());
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        assert_eq!(1, pool.stats().panicked);
+    }
+
+    #[test]
+    fn thread_pool_spawn_future_cancelled_at_shutdown() {
+        let mut pool = ThreadPool::new().unwrap();
+        let gate = Gate::default();
+
+        let handle = pool.spawn_future(gate.clone()).unwrap();
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        pool.shutdown(Duration::from_secs(5));
+
+        // The gate still holds the future's waker.
+        assert_eq!(Err(JoinError::Cancelled), handle.join_timeout(Duration::from_secs(5)));
+        drop(gate);
+    }
+
+    #[test]
+    fn block_on_waits_for_wakeup() {
+        let gate = Gate::default();
+        let opener = gate.clone();
+        let opening = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            opener.open();
+        });
+
+        assert_eq!(7, block_on(async move {
+            gate.await;
+            7
+        }));
+        opening.join().unwrap();
+    }
+
+    #[test]
+    fn thread_pool_execute_fair_counts_against_queue_capacity() {
+        let (pool, release) = blocked_pool_with(ThreadPool::builder().queue_capacity(2));
+
+        pool.execute_fair("a", || {}).unwrap();
+        pool.execute_fair("b", || {}).unwrap();
+
+        assert!(matches!(pool.execute_fair("c", || {}), Err(PoolError::QueueFull)));
+        assert_eq!(0, pool.fair_queue_len("c"));
+        drop(release);
+        pool.wait_idle();
+        assert_eq!(3, pool.stats().completed);
+    }
+
+    #[test]
+    fn thread_pool_blocked_producer_is_woken_for_every_free_slot() {
+        // given
+        let pool = ThreadPool::builder().size(2).queue_capacity(1).build().unwrap();
+        let (done_sender, done) = mpsc::channel();
+
+        // when
+        let producer = thread::spawn(move || {
+            for _ in 0..10_000 {
+                pool.execute(|| {}).unwrap();
+            }
+            done_sender.send(()).unwrap();
+        });
+
+        // then
+        assert_eq!(Ok(()), done.recv_timeout(Duration::from_secs(10)));
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn thread_pool_discard_oldest_discards_evicted_fair_job() {
+        // given
+        let (pool, release) = blocked_pool_with(ThreadPool::builder()
+            .queue_capacity(1)
+            .rejection_policy(RejectionPolicy::DiscardOldest));
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&ran);
+        pool.execute_fair("a", move || { counter.fetch_add(1, Ordering::SeqCst); }).unwrap();
+
+        // when
+        pool.execute(|| {}).unwrap();
+
+        // then
+        assert_eq!(0, pool.fair_queue_len("a"));
+        drop(release);
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+        assert_eq!(0, ran.load(Ordering::SeqCst));
+        assert_eq!(1, pool.stats().discarded);
+    }
+
+    #[test]
+    fn thread_pool_runs_outside_jobs_next_to_self_resubmitting_job() {
+        fn resubmit(pool: PoolHandle, stop: Arc<AtomicBool>) {
+            if !stop.load(Ordering::SeqCst) {
+                let handle = pool.clone();
+                let _ = handle.execute(move || resubmit(pool, stop));
+            }
+        }
+
+        for priority in [Priority::Normal, Priority::Low] {
+            // given
+            let pool = ThreadPool::new().unwrap();
+            let stop = Arc::new(AtomicBool::new(false));
+            let handle = pool.handle();
+            let looping = Arc::clone(&stop);
+            pool.execute(move || resubmit(handle, looping)).unwrap();
+
+            // when
+            let (sender, receiver) = mpsc::channel();
+            let stopper = Arc::clone(&stop);
+            pool.execute_with_priority(priority, move || {
+                stopper.store(true, Ordering::SeqCst);
+                sender.send(()).unwrap();
+            }).unwrap();
+
+            // then
+            let result = receiver.recv_timeout(Duration::from_secs(5));
+            stop.store(true, Ordering::SeqCst);
+            assert_eq!(Ok(()), result);
+        }
     }
 }
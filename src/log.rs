@@ -0,0 +1,79 @@
+use std::fmt::{Arguments, Display, Formatter};
+use std::io::Write;
+
+/// Severity of a log record, from most to least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        })
+    }
+}
+
+/// A diagnostic emitted by the pool.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub level: Level,
+    pub message: Arguments<'a>,
+    /// Worker the record is about, if any.
+    pub worker_id: Option<usize>,
+    /// Job the record is about, if any.
+    pub job_id: Option<u64>,
+}
+
+/// Receives the pool's diagnostics. Set it with `ThreadPoolBuilder::logger`; without one the
+/// pool logs nothing.
+///
+/// `log` is called on the thread that produced the record, often a worker, so it should not
+/// block for long.
+pub trait Logger: Send + Sync {
+    /// Returns `false` to skip records of `level` before they are built.
+    fn enabled(&self, level: Level) -> bool {
+        let _ = level;
+        true
+    }
+
+    fn log(&self, record: &Record<'_>);
+}
+
+/// Writes records up to a maximum level to standard error, one line each.
+#[derive(Debug, Clone, Copy)]
+pub struct StderrLogger {
+    level: Level,
+}
+
+impl StderrLogger {
+    pub fn new(level: Level) -> StderrLogger {
+        StderrLogger { level }
+    }
+}
+
+impl Logger for StderrLogger {
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let mut line = format!("[{}]", record.level);
+        if let Some(worker_id) = record.worker_id {
+            line += &format!(" worker={worker_id}");
+        }
+        if let Some(job_id) = record.job_id {
+            line += &format!(" job={job_id}");
+        }
+        let _ = writeln!(std::io::stderr().lock(), "{line} {}", record.message);
+    }
+}
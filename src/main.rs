@@ -3,33 +3,46 @@ use std::{
     fs,
     io::{BufReader, prelude::*},
     net::{TcpListener, TcpStream},
-    thread,
     time::Duration,
 };
-use web_server_rust_book::ThreadPool;
+use web_server_rust_book::{CancellationToken, Level, PoolError, StderrLogger, ThreadPool};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let listener = TcpListener::bind("127.0.0.1:7878")?;
-    let pool = ThreadPool::build(4).unwrap_or(ThreadPool::new());
+    let pool = ThreadPool::builder()
+        .size(4)
+        .max_size(16)
+        .queue_capacity(64)
+        .max_queued_per_key(8)
+        .logger(StderrLogger::new(Level::Info))
+        .build()?;
 
     for stream in listener.incoming() {
         let stream = stream.unwrap();
-
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+        // The client may already have disconnected.
+        let Ok(client) = stream.peer_addr().map(|addr| addr.ip()) else {
+            continue;
+        };
+        let token = pool.cancellation_token().child_token();
+
+        match pool.execute_fair(client, move || handle_connection(stream, &token)) {
+            Err(PoolError::QueueFull) => continue,
+            result => result?,
+        }
     }
     Ok(())
 }
 
-fn handle_connection(mut stream: TcpStream) {
+fn handle_connection(mut stream: TcpStream, token: &CancellationToken) {
     let buf_reader = BufReader::new(&mut stream);
     let request_line = buf_reader.lines().next().unwrap().unwrap();
 
     let (status_line, filename) = match request_line.as_str() {
         "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
         "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
+            if token.wait_timeout(Duration::from_secs(5)) {
+                return;
+            }
             ("HTTP/1.1 200 OK", "hello.html")
         }
         _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
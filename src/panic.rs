@@ -0,0 +1,23 @@
+use std::any::Any;
+
+/// Maximum number of panics kept by a pool; older records are discarded first.
+pub(crate) const PANIC_LOG_CAPACITY: usize = 64;
+
+/// Record of a job that panicked on a worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobPanic {
+    pub job_id: u64,
+    pub worker_id: usize,
+    pub message: String,
+}
+
+/// Extracts the message from a panic payload.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
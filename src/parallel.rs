@@ -0,0 +1,24 @@
+/// Number of chunks per worker, so faster workers can pick up the chunks of slower ones.
+const CHUNKS_PER_WORKER: usize = 4;
+
+/// Collects `items` and splits them into consecutive chunks for `workers` threads.
+///
+/// Returns no chunks for no items and never more chunks than items.
+pub(crate) fn split<I>(items: I, workers: usize) -> Vec<Vec<I::Item>>
+    where I: IntoIterator,
+{
+    let mut items: Vec<I::Item> = items.into_iter().collect();
+    if items.is_empty() {
+        return Vec::new();
+    }
+    let chunks = (workers.max(1) * CHUNKS_PER_WORKER).min(items.len());
+    let chunk_size = items.len().div_ceil(chunks);
+
+    let mut split = Vec::with_capacity(chunks);
+    while items.len() > chunk_size {
+        let rest = items.split_off(chunk_size);
+        split.push(std::mem::replace(&mut items, rest));
+    }
+    split.push(items);
+    split
+}
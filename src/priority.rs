@@ -0,0 +1,35 @@
+/// Scheduling priority of a job passed to `ThreadPool::execute_with_priority`.
+///
+/// Workers prefer higher priorities, but a waiting lower priority job is picked after
+/// higher priority jobs have been preferred over it a few times, so it is never starved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    /// Every priority, from highest to lowest.
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Number of queued jobs at each priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueLengths {
+    pub high: usize,
+    pub normal: usize,
+    pub low: usize,
+}
+
+impl QueueLengths {
+    /// Total number of queued jobs.
+    pub fn total(&self) -> usize {
+        self.high + self.normal + self.low
+    }
+}
@@ -0,0 +1,592 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::priority::{Priority, QueueLengths};
+use crate::worker::Job;
+
+/// Error returned by `ThreadPool::try_execute` and `ThreadPool::execute_timeout`.
+///
+/// The rejected job is handed back so the caller can run it elsewhere or retry.
+pub enum TryExecuteError<F> {
+    /// The queue is at capacity.
+    Full(F),
+    /// The queue stayed at capacity until the timeout elapsed.
+    Timeout(F),
+    /// The pool has been shut down.
+    ShutDown(F),
+}
+
+impl<F> TryExecuteError<F> {
+    /// Returns the job that could not be queued.
+    pub fn into_inner(self) -> F {
+        match self {
+            TryExecuteError::Full(f) | TryExecuteError::Timeout(f) | TryExecuteError::ShutDown(f) => f,
+        }
+    }
+}
+
+impl<F> Debug for TryExecuteError<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryExecuteError::Full(_) => f.write_str("Full(..)"),
+            TryExecuteError::Timeout(_) => f.write_str("Timeout(..)"),
+            TryExecuteError::ShutDown(_) => f.write_str("ShutDown(..)"),
+        }
+    }
+}
+
+impl<F> Display for TryExecuteError<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryExecuteError::Full(_) => f.write_str("job queue is full"),
+            TryExecuteError::Timeout(_) => f.write_str("timed out waiting for space in the job queue"),
+            TryExecuteError::ShutDown(_) => f.write_str("pool has been shut down"),
+        }
+    }
+}
+
+impl<F> Error for TryExecuteError<F> {}
+
+pub(crate) enum PushError {
+    Full,
+    Closed,
+}
+
+pub(crate) enum Pop {
+    /// A job, and whether the worker yielded or slept before finding it.
+    Job(Job, bool),
+    /// No job arrived before the timeout.
+    TimedOut,
+    /// The queue is closed and empty.
+    Closed,
+}
+
+/// Upper bound on the number of jobs a worker moves from the injector to its local deque at once.
+const MAX_BATCH: usize = 32;
+
+/// Maximum number of times an idle worker yields and looks for jobs again before going to sleep.
+///
+/// Each worker adapts its own limit: it doubles when a job turned up while spinning and halves
+/// when the worker had to sleep anyway, so workers of an idle pool stop burning CPU time.
+const SPIN_LIMIT: usize = 16;
+
+/// Number of times a waiting job can be passed over for a higher priority one before
+/// its priority is served first.
+const STARVATION_LIMIT: usize = 8;
+
+/// A worker looks at the injector before its own deque every this many jobs, so jobs that
+/// keep resubmitting themselves locally cannot starve jobs submitted from outside.
+const INJECTOR_INTERVAL: usize = 31;
+
+const LEVELS: usize = Priority::ALL.len();
+const HIGH: usize = 0;
+const NORMAL: usize = 1;
+const LOW: usize = 2;
+
+thread_local! {
+    /// Local deque of the worker running on this thread, tagged with the address of its queue.
+    static CURRENT: RefCell<Option<(usize, Arc<Local>)>> = const { RefCell::new(None) };
+}
+
+/// Per worker deque. The owner pops from the front; thieves take from the back.
+#[derive(Default)]
+pub(crate) struct Local {
+    jobs: Mutex<VecDeque<Job>>,
+    /// Number of times the owner looked for a job, to check the injector periodically.
+    ticks: AtomicUsize,
+    /// Current spin limit of the owner, between 1 and `SPIN_LIMIT`.
+    spin_limit: AtomicUsize,
+}
+
+impl Local {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Job>> {
+        self.jobs.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+struct State {
+    /// Global queues, one per priority, for jobs submitted from outside the pool's workers
+    /// and for every job that is not `Priority::Normal`.
+    injector: [VecDeque<Job>; LEVELS],
+    /// Per priority, how many times a higher priority was served while jobs were waiting.
+    skipped: [usize; LEVELS],
+    /// Number of workers sleeping in `pop`, mirrored in `Queue::sleepers`.
+    idle: usize,
+    /// Number of sleeping workers that have been notified but have not woken up yet.
+    notified: usize,
+}
+
+impl State {
+    /// Chooses the injector level to pop from: the highest non-empty one, unless a lower
+    /// level has been passed over `STARVATION_LIMIT` times.
+    fn next_level(&mut self) -> Option<usize> {
+        let highest = (0..LEVELS).find(|level| !self.injector[*level].is_empty())?;
+        let level = (highest + 1..LEVELS).rev()
+            .find(|level| self.skipped[*level] >= STARVATION_LIMIT && !self.injector[*level].is_empty())
+            .unwrap_or(highest);
+
+        for lower in level + 1..LEVELS {
+            if !self.injector[lower].is_empty() {
+                self.skipped[lower] += 1;
+            }
+        }
+        self.skipped[level] = 0;
+        Some(level)
+    }
+
+    fn has_injected(&self) -> bool {
+        self.injector.iter().any(|jobs| !jobs.is_empty())
+    }
+
+    /// Records that a sleeping worker woke up, or decided not to sleep after all.
+    fn wake_up(&mut self, sleepers: &AtomicUsize) {
+        self.idle -= 1;
+        self.notified = self.notified.saturating_sub(1);
+        sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wakes a sleeping worker unless every sleeper already has a wakeup pending.
+    fn wake_one(&mut self, not_empty: &Condvar) {
+        if self.idle > self.notified {
+            self.notified += 1;
+            not_empty.notify_one();
+        }
+    }
+}
+
+/// Work-stealing job queue shared by the pool and its workers, optionally bounded.
+///
+/// Jobs submitted from outside the pool go to a global injector; normal priority jobs submitted
+/// from a worker go to that worker's local deque. A worker takes jobs from its own deque first,
+/// then moves a batch from the injector, then steals half of another worker's deque. Pushing to
+/// a worker's own deque of an unbounded queue does not take the global lock; every other push
+/// and every injector pop does. High priority jobs waiting in the
+/// injector are taken before the worker's own deque, and every `INJECTOR_INTERVAL` jobs the
+/// injector is checked first regardless of priority.
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<usize>,
+    /// Jobs in the injector and all local deques, per priority.
+    ///
+    /// A worker going to sleep increments `sleepers` before checking that no job is queued,
+    /// and a producer counts its job before checking `sleepers`, so one of them always sees
+    /// the other: either the worker finds the job or the producer wakes it.
+    lengths: [AtomicUsize; LEVELS],
+    /// Number of workers sleeping in `pop` or about to. Only changed with `state` locked.
+    sleepers: AtomicUsize,
+    /// Number of producers waiting for room in a full queue. A producer increments it before
+    /// checking the queue length again, and `taken` uncounts the job before checking it, so
+    /// one of them sees the other.
+    blocked: AtomicUsize,
+    /// While set, workers find no jobs and sleep. Only changed with `state` locked.
+    paused: AtomicBool,
+    /// Set once the queue stops accepting jobs. Only changed with `state` locked.
+    closed: AtomicBool,
+    locals: RwLock<Vec<Arc<Local>>>,
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: Option<usize>) -> Queue {
+        Queue {
+            state: Mutex::new(State {
+                injector: Default::default(),
+                skipped: [0; LEVELS],
+                idle: 0,
+                notified: 0,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+            lengths: Default::default(),
+            sleepers: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            paused: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            locals: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn id(&self) -> usize {
+        self as *const Queue as usize
+    }
+
+    /// Returns the calling worker's local deque if the caller is a worker of this queue.
+    fn current_local(&self) -> Option<Arc<Local>> {
+        CURRENT.with(|current| match &*current.borrow() {
+            Some((id, local)) if *id == self.id() => Some(Arc::clone(local)),
+            _ => None,
+        })
+    }
+
+    /// Returns `true` if the caller is a worker of this queue.
+    pub(crate) fn is_worker(&self) -> bool {
+        self.current_local().is_some()
+    }
+
+    /// Queues `job` on the caller's local deque or the injector. `state` must be locked.
+    fn insert(&self, state: &mut State, job: Job) {
+        let level = job.priority.index();
+        self.lengths[level].fetch_add(1, Ordering::SeqCst);
+
+        match self.current_local() {
+            Some(local) if level == NORMAL => local.lock().push_back(job),
+            _ => state.injector[level].push_back(job),
+        }
+        state.wake_one(&self.not_empty);
+    }
+
+    /// Removes `job` from the queued counts.
+    fn uncount(&self, job: &Job) {
+        self.lengths[job.priority.index()].fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Returns the number of jobs in the injector and all local deques.
+    fn queued(&self) -> usize {
+        self.lengths.iter().map(|length| length.load(Ordering::SeqCst)).sum()
+    }
+
+    fn has_room(&self) -> bool {
+        self.capacity.is_none_or(|capacity| self.queued() < capacity)
+    }
+
+    /// Records that a job left the queue and wakes a blocked producer if there is one.
+    fn taken(&self, job: &Job) {
+        self.uncount(job);
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            let _state = self.lock();
+            self.not_full.notify_one();
+        }
+    }
+
+    /// Waits for a free slot, then queues the job built from `item`.
+    ///
+    /// With no `deadline` it blocks until space is available; a deadline in the past makes it
+    /// return `PushError::Full` immediately. `item` is returned if it could not be queued.
+    pub(crate) fn push<T>(&self, item: T, deadline: Option<Instant>, into_job: impl FnOnce(T) -> Job) -> Result<(), (T, PushError)> {
+        let mut state = self.lock();
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return Err((item, PushError::Closed));
+            }
+            if self.has_room() {
+                break;
+            }
+
+            self.blocked.fetch_add(1, Ordering::SeqCst);
+            if self.has_room() {
+                self.blocked.fetch_sub(1, Ordering::SeqCst);
+                break;
+            }
+            let waited = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        None
+                    } else {
+                        Some(self.not_full.wait_timeout(state, remaining).unwrap_or_else(|err| err.into_inner()).0)
+                    }
+                }
+                None => Some(self.not_full.wait(state).unwrap_or_else(|err| err.into_inner())),
+            };
+            self.blocked.fetch_sub(1, Ordering::SeqCst);
+
+            state = match waited {
+                Some(state) => state,
+                None => return Err((item, PushError::Full)),
+            };
+        }
+
+        self.insert(&mut state, into_job(item));
+        Ok(())
+    }
+
+    /// Queues the job built from `item`, evicting a queued job if the queue is full.
+    ///
+    /// The evicted job is the oldest one of the lowest priority, normal priority jobs on
+    /// worker deques coming after the injector's normal priority jobs.
+    /// Returns the evicted job so it can be dropped outside the lock.
+    pub(crate) fn push_evicting<T>(&self, item: T, into_job: impl FnOnce(T) -> Job) -> Result<Option<Job>, (T, PushError)> {
+        let mut state = self.lock();
+        if self.closed.load(Ordering::SeqCst) {
+            return Err((item, PushError::Closed));
+        }
+
+        let evicted = match self.capacity {
+            Some(capacity) if self.queued() >= capacity => {
+                let evicted = state.injector[LOW].pop_front()
+                    .or_else(|| state.injector[NORMAL].pop_front())
+                    .or_else(|| {
+                        let locals = self.locals.read().unwrap_or_else(|err| err.into_inner());
+                        locals.iter().find_map(|local| local.lock().pop_front())
+                    })
+                    .or_else(|| state.injector[HIGH].pop_front());
+                if let Some(job) = &evicted {
+                    self.uncount(job);
+                }
+                evicted
+            }
+            _ => None,
+        };
+        self.insert(&mut state, into_job(item));
+        Ok(evicted)
+    }
+
+    /// Queues the normal priority job built from `item` on the calling worker's deque without
+    /// taking the `state` lock. Only possible for an unbounded, open queue; otherwise `item`
+    /// is returned to be queued with `push`.
+    pub(crate) fn push_local<T>(&self, item: T, into_job: impl FnOnce(T) -> Job) -> Result<(), T> {
+        if self.capacity.is_some() || self.closed.load(Ordering::SeqCst) {
+            return Err(item);
+        }
+        let Some(local) = self.current_local() else {
+            return Err(item);
+        };
+        let job = into_job(item);
+        debug_assert_eq!(NORMAL, job.priority.index());
+
+        self.lengths[NORMAL].fetch_add(1, Ordering::SeqCst);
+        local.lock().push_back(job);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            self.lock().wake_one(&self.not_empty);
+        }
+        Ok(())
+    }
+
+    /// Queues a job without waiting for room, for jobs the pool admits by other means than
+    /// the capacity.
+    ///
+    /// A closed queue only takes the job from one of its workers, which will pop it before
+    /// exiting; otherwise the job is returned.
+    pub(crate) fn requeue(&self, job: Job) -> Result<(), Job> {
+        let mut state = self.lock();
+        if self.closed.load(Ordering::SeqCst) && self.current_local().is_none() {
+            return Err(job);
+        }
+        self.insert(&mut state, job);
+        Ok(())
+    }
+
+    /// Registers the calling thread as a worker and returns its local deque.
+    pub(crate) fn register(&self) -> Arc<Local> {
+        let local = Arc::new(Local { spin_limit: AtomicUsize::new(SPIN_LIMIT), ..Local::default() });
+        self.locals.write().unwrap_or_else(|err| err.into_inner()).push(Arc::clone(&local));
+        CURRENT.with(|current| *current.borrow_mut() = Some((self.id(), Arc::clone(&local))));
+        local
+    }
+
+    /// Removes a worker's local deque, handing its remaining jobs back to the injector.
+    pub(crate) fn unregister(&self, local: &Arc<Local>) {
+        CURRENT.with(|current| *current.borrow_mut() = None);
+        self.locals.write().unwrap_or_else(|err| err.into_inner())
+            .retain(|other| !Arc::ptr_eq(other, local));
+
+        let orphans: Vec<Job> = local.lock().drain(..).collect();
+        if !orphans.is_empty() {
+            let mut state = self.lock();
+            for job in orphans {
+                state.injector[job.priority.index()].push_back(job);
+            }
+            self.not_empty.notify_all();
+        }
+    }
+
+    /// Blocks until a job is available, the queue is closed and empty, or `timeout` elapses.
+    pub(crate) fn pop(&self, local: &Arc<Local>, timeout: Option<Duration>) -> Pop {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let spin_limit = local.spin_limit.load(Ordering::Relaxed);
+        let mut spins = 0;
+        let mut slept = false;
+        loop {
+            if let Some(job) = self.find(local) {
+                if spins > 0 && !slept {
+                    local.spin_limit.store((spin_limit * 2).min(SPIN_LIMIT), Ordering::Relaxed);
+                }
+                self.taken(&job);
+                return Pop::Job(job, spins > 0 || slept);
+            }
+            // Give producers a chance to submit more work before paying for a sleep and wakeup.
+            if spins < spin_limit {
+                spins += 1;
+                thread::yield_now();
+                continue;
+            }
+
+            let mut state = self.lock();
+            state.idle += 1;
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            let ready = self.queued() > 0 && !self.paused.load(Ordering::SeqCst);
+            if ready || self.closed.load(Ordering::SeqCst) {
+                state.wake_up(&self.sleepers);
+                match ready {
+                    true => continue,
+                    false => return Pop::Closed,
+                }
+            }
+
+            if !slept {
+                slept = true;
+                local.spin_limit.store((spin_limit / 2).max(1), Ordering::Relaxed);
+            }
+            state = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        state.wake_up(&self.sleepers);
+                        return Pop::TimedOut;
+                    }
+                    self.not_empty.wait_timeout(state, remaining).unwrap_or_else(|err| err.into_inner()).0
+                }
+                None => self.not_empty.wait(state).unwrap_or_else(|err| err.into_inner()),
+            };
+            state.wake_up(&self.sleepers);
+        }
+    }
+
+    fn find(&self, local: &Arc<Local>) -> Option<Job> {
+        if self.paused.load(Ordering::SeqCst) {
+            return None;
+        }
+        // Only the owner changes `ticks`, so it needs no atomic increment.
+        let tick = local.ticks.load(Ordering::Relaxed);
+        local.ticks.store(tick.wrapping_add(1), Ordering::Relaxed);
+        if self.lengths[HIGH].load(Ordering::SeqCst) == 0 && !tick.is_multiple_of(INJECTOR_INTERVAL) {
+            if let Some(job) = local.lock().pop_front() {
+                return Some(job);
+            }
+        }
+        if self.queued() == 0 {
+            return None;
+        }
+        self.pop_injector(local)
+            .or_else(|| local.lock().pop_front())
+            .or_else(|| self.steal(local))
+    }
+
+    /// Takes a job from the injector. A normal priority job brings a batch of the following
+    /// normal priority jobs to `local`.
+    ///
+    /// If jobs are left over, another sleeping worker is woken to help, so producers only
+    /// need to wake one worker however fast they submit.
+    fn pop_injector(&self, local: &Local) -> Option<Job> {
+        let mut state = self.lock();
+        let level = state.next_level()?;
+        let job = state.injector[level].pop_front()?;
+
+        let mut batch = 0;
+        if level == NORMAL {
+            let workers = self.locals.read().unwrap_or_else(|err| err.into_inner()).len().max(1);
+            batch = (state.injector[NORMAL].len() / workers).min(MAX_BATCH);
+            if batch > 0 {
+                local.lock().extend(state.injector[NORMAL].drain(..batch));
+            }
+        }
+        if batch > 0 || state.has_injected() {
+            state.wake_one(&self.not_empty);
+        }
+        Some(job)
+    }
+
+    /// Steals half of the jobs from the back of another worker's deque.
+    fn steal(&self, local: &Arc<Local>) -> Option<Job> {
+        let locals = self.locals.read().unwrap_or_else(|err| err.into_inner());
+
+        for victim in locals.iter().filter(|victim| !Arc::ptr_eq(victim, local)) {
+            let mut stolen = {
+                let mut jobs = victim.lock();
+                let len = jobs.len();
+                if len == 0 {
+                    continue;
+                }
+                jobs.split_off(len / 2)
+            };
+
+            let job = stolen.pop_front();
+            if !stolen.is_empty() {
+                local.lock().extend(stolen);
+            }
+            return job;
+        }
+        None
+    }
+
+    /// Stops accepting jobs and wakes every blocked producer and worker.
+    ///
+    /// Jobs already queued can still be popped; a paused queue is resumed so workers can drain it.
+    pub(crate) fn close(&self) {
+        {
+            let _state = self.lock();
+            self.closed.store(true, Ordering::SeqCst);
+            self.paused.store(false, Ordering::SeqCst);
+        }
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    /// Removes every job from the injector and the local deques. The jobs are returned so
+    /// they can be dropped outside the locks.
+    pub(crate) fn drain(&self) -> Vec<Job> {
+        let mut jobs = Vec::new();
+        {
+            let mut state = self.lock();
+            for injector in &mut state.injector {
+                jobs.extend(injector.drain(..));
+            }
+            let locals = self.locals.read().unwrap_or_else(|err| err.into_inner());
+            for local in locals.iter() {
+                jobs.extend(local.lock().drain(..));
+            }
+        }
+        for job in &jobs {
+            self.uncount(job);
+        }
+        jobs
+    }
+
+    /// Stops workers from taking jobs until `resume`; they sleep in `pop` instead.
+    ///
+    /// Has no effect on a closed queue.
+    pub(crate) fn pause(&self) {
+        let _state = self.lock();
+        if !self.closed.load(Ordering::SeqCst) {
+            self.paused.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Lets workers take jobs again after `pause`.
+    pub(crate) fn resume(&self) {
+        let _state = self.lock();
+        self.paused.store(false, Ordering::SeqCst);
+        self.not_empty.notify_all();
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of queued jobs at each priority.
+    pub(crate) fn lengths(&self) -> QueueLengths {
+        QueueLengths {
+            high: self.lengths[HIGH].load(Ordering::SeqCst),
+            normal: self.lengths[NORMAL].load(Ordering::SeqCst),
+            low: self.lengths[LOW].load(Ordering::SeqCst),
+        }
+    }
+
+    /// Returns the number of queued jobs that no idle worker is waiting to take.
+    pub(crate) fn backlog(&self) -> usize {
+        let state = self.lock();
+        self.queued().saturating_sub(state.idle)
+    }
+}
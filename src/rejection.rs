@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What `ThreadPool::execute` does with a job when the queue is at capacity.
+///
+/// `try_execute` and `execute_timeout` ignore the policy and always hand the job back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RejectionPolicy {
+    /// Block the caller until there is room in the queue.
+    #[default]
+    Block,
+    /// Reject the job; `execute` returns `PoolError::QueueFull`.
+    Abort,
+    /// Run the job on the calling thread. A panic in the job propagates to the caller.
+    CallerRuns,
+    /// Drop the job being submitted.
+    DiscardNewest,
+    /// Drop the oldest queued job of the lowest waiting priority to make room for the new one.
+    DiscardOldest,
+}
+
+/// Number of jobs handled by each `RejectionPolicy` outcome since the pool was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RejectionStats {
+    pub aborted: u64,
+    pub caller_runs: u64,
+    pub discarded_newest: u64,
+    pub discarded_oldest: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RejectionCounters {
+    pub(crate) aborted: AtomicU64,
+    pub(crate) caller_runs: AtomicU64,
+    pub(crate) discarded_newest: AtomicU64,
+    pub(crate) discarded_oldest: AtomicU64,
+}
+
+impl RejectionCounters {
+    pub(crate) fn snapshot(&self) -> RejectionStats {
+        RejectionStats {
+            aborted: self.aborted.load(Ordering::Relaxed),
+            caller_runs: self.caller_runs.load(Ordering::Relaxed),
+            discarded_newest: self.discarded_newest.load(Ordering::Relaxed),
+            discarded_oldest: self.discarded_oldest.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::PoolHandle;
+use crate::worker::Task;
+
+/// Jobs that may borrow from the stack of `ThreadPool::scope`, created by the pool.
+///
+/// `'scope` is the lifetime of the scope itself and `'env` the lifetime of the data the jobs
+/// borrow, as in `std::thread::Scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'env PoolHandle,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+#[derive(Default)]
+struct ScopeState {
+    inner: Mutex<Inner>,
+    /// Signalled when a job is added or finishes.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Jobs not picked up yet, by a worker or by the thread waiting for the scope.
+    jobs: VecDeque<Task>,
+    /// Jobs that have not finished yet, queued or running.
+    pending: usize,
+    /// Payload of the first panic, resumed when the scope ends.
+    panic: Option<Box<dyn Any + Send + 'static>>,
+}
+
+impl ScopeState {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Runs the oldest job that nobody has picked up yet. Returns `false` if there was none.
+    fn run_one(&self) -> bool {
+        let Some(task) = self.lock().jobs.pop_front() else {
+            return false;
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(task));
+
+        let mut inner = self.lock();
+        if let Err(payload) = result {
+            inner.panic.get_or_insert(payload);
+        }
+        inner.pending -= 1;
+        self.changed.notify_all();
+        true
+    }
+
+    /// Helps running the scope's jobs until every one of them has finished.
+    fn wait(&self) {
+        loop {
+            if self.run_one() {
+                continue;
+            }
+            let inner = self.lock();
+            if inner.pending == 0 {
+                return;
+            }
+            if inner.jobs.is_empty() {
+                drop(self.changed.wait(inner).unwrap_or_else(|err| err.into_inner()));
+            }
+        }
+    }
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Executes a function on the pool that may borrow anything that outlives the scope
+    ///
+    /// The job is run by a worker, or by the thread waiting for the scope to end if no worker
+    /// has picked it up by then. Jobs can therefore not be lost: if the pool has been shut down
+    /// or rejects the job, it still runs before `scope` returns.
+    pub fn execute<F>(&'scope self, f: F)
+        where F: FnOnce() + Send + 'scope,
+    {
+        let task: Box<dyn FnOnce() + Send + 'scope> = Box::new(f);
+        // SAFETY: `ThreadPool::scope` does not return before every job has run, so the task
+        // never outlives `'scope`. The transmute only erases the lifetime.
+        let task: Task = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Task>(task) };
+
+        {
+            let mut inner = self.state.lock();
+            inner.jobs.push_back(task);
+            inner.pending += 1;
+        }
+        self.state.changed.notify_all();
+
+        let state = Arc::clone(&self.state);
+        let _ = self.pool.execute(move || {
+            state.run_one();
+        });
+    }
+}
+
+/// Runs `f` with a new scope on `pool` and waits for all of its jobs. See `ThreadPool::scope`.
+pub(crate) fn run<'env, F, T>(pool: &'env PoolHandle, f: F) -> T
+    where F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope {
+        pool,
+        state: Arc::new(ScopeState::default()),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    scope.state.wait();
+
+    match result {
+        Err(payload) => panic::resume_unwind(payload),
+        Ok(value) => match scope.state.lock().panic.take() {
+            Some(payload) => panic::resume_unwind(payload),
+            None => value,
+        },
+    }
+}
@@ -0,0 +1,59 @@
+/// What happens to jobs still waiting in the queue when a pool shuts down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShutdownPolicy {
+    /// Run every queued job before the workers exit, or until the shutdown deadline passes.
+    #[default]
+    Drain,
+    /// Discard queued jobs; only jobs already running are finished.
+    Abandon,
+}
+
+use crate::stats::RetiredWorkers;
+
+/// Outcome of a single worker after shutdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerReport {
+    pub id: usize,
+    /// Jobs the worker ran to completion.
+    pub completed: usize,
+    /// Queued jobs the worker discarded because of `ShutdownPolicy::Abandon`.
+    pub abandoned: usize,
+    /// Jobs that panicked on this worker.
+    pub panicked: usize,
+    /// `false` if the worker was still running when the shutdown deadline passed.
+    pub finished: bool,
+}
+
+/// Summary returned by `ThreadPool::shutdown`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ShutdownReport {
+    pub workers: Vec<WorkerReport>,
+    /// Queued jobs discarded by the shutdown itself because workers missed the deadline.
+    pub abandoned: usize,
+    /// Workers that retired before the shutdown and are not in `workers`.
+    pub retired: RetiredWorkers,
+    /// Workers the watchdog replaced, which are not in `workers` either.
+    pub lost: RetiredWorkers,
+}
+
+impl ShutdownReport {
+    /// Total number of jobs completed by all workers, including retired and lost ones.
+    pub fn completed(&self) -> usize {
+        self.retired.completed + self.lost.completed + self.workers.iter().map(|worker| worker.completed).sum::<usize>()
+    }
+
+    /// Total number of queued jobs discarded, by the workers or when the deadline passed.
+    pub fn abandoned(&self) -> usize {
+        self.abandoned + self.workers.iter().map(|worker| worker.abandoned).sum::<usize>()
+    }
+
+    /// Total number of jobs that panicked, including on retired and lost workers.
+    pub fn panicked(&self) -> usize {
+        self.retired.panicked + self.lost.panicked + self.workers.iter().map(|worker| worker.panicked).sum::<usize>()
+    }
+
+    /// Returns `true` if every worker exited before the deadline.
+    pub fn is_finished(&self) -> bool {
+        self.workers.iter().all(|worker| worker.finished)
+    }
+}
@@ -0,0 +1,162 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{JobHandle, PoolError, ThreadPool};
+
+static NEXT_STATE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// `Init::id` of every `StatePool` that has not been dropped.
+static LIVE: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
+/// Number of `StatePool`s dropped so far.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// States of every `StatePool` that has run a job on this thread, by `Init::id`.
+    static STATES: RefCell<Vec<(usize, Box<dyn Any>)>> = const { RefCell::new(Vec::new()) };
+
+    /// Value of `DROPPED` when this thread last checked for states of dropped pools.
+    static SEEN_DROPPED: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Init<S> {
+    id: usize,
+    init: Box<dyn Fn() -> S + Send + Sync + 'static>,
+}
+
+/// A `ThreadPool` whose jobs receive a per-thread state, created by
+/// `ThreadPool::build_with_state` or `ThreadPoolBuilder::build_with_state`.
+///
+/// Every thread that runs a job of the pool builds its own state with the init function the
+/// first time, and keeps it until the thread exits or the pool is dropped. That is normally a
+/// worker, but can be the caller with `RejectionPolicy::CallerRuns` or while it waits for a
+/// `scope`. A job that panics drops the state of its thread; the next job builds a new one.
+///
+/// The states of other threads cannot be dropped from the thread dropping the pool, since `S`
+/// need not be `Send`. A thread drops them the next time it runs a job of any `StatePool`.
+///
+/// The remaining `ThreadPool` functions are available through `Deref`.
+pub struct StatePool<S> {
+    pool: ThreadPool,
+    init: Arc<Init<S>>,
+}
+
+impl<S: 'static> StatePool<S> {
+    pub(crate) fn new<I>(pool: ThreadPool, init: I) -> StatePool<S>
+        where I: Fn() -> S + Send + Sync + 'static,
+    {
+        let id = NEXT_STATE_ID.fetch_add(1, Ordering::Relaxed);
+        lock_live().insert(id);
+        StatePool { pool, init: Arc::new(Init { id, init: Box::new(init) }) }
+    }
+
+    /// Executes a function with the state of the thread that runs it
+    ///
+    /// # Result<(), PoolError>
+    ///
+    /// The `execute` function fails like `ThreadPool::execute`.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolError>
+        where F: FnOnce(&mut S) + Send + 'static,
+    {
+        let init = Arc::clone(&self.init);
+        self.pool.execute(move || with_state(&init, f))
+    }
+
+    /// Executes a function with the state of the thread that runs it and returns a handle
+    /// to its result
+    ///
+    /// # Result<JobHandle<T>, PoolError>
+    ///
+    /// The `submit` function fails like `ThreadPool::execute`.
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, PoolError>
+        where F: FnOnce(&mut S) -> T + Send + 'static,
+              T: Send + 'static,
+    {
+        let init = Arc::clone(&self.init);
+        self.pool.submit(move || with_state(&init, f))
+    }
+}
+
+impl<S> Drop for StatePool<S> {
+    fn drop(&mut self) {
+        lock_live().remove(&self.init.id);
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+        drop_stale_states();
+    }
+}
+
+impl<S> Deref for StatePool<S> {
+    type Target = ThreadPool;
+
+    fn deref(&self) -> &ThreadPool {
+        &self.pool
+    }
+}
+
+impl<S> DerefMut for StatePool<S> {
+    fn deref_mut(&mut self) -> &mut ThreadPool {
+        &mut self.pool
+    }
+}
+
+fn lock_live() -> MutexGuard<'static, BTreeSet<usize>> {
+    LIVE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Drops this thread's states of `StatePool`s that have been dropped.
+fn drop_stale_states() {
+    let live = lock_live();
+    let stale: Vec<_> = STATES.with_borrow_mut(|states| {
+        let (kept, stale) = mem::take(states).into_iter().partition(|(id, _)| live.contains(id));
+        *states = kept;
+        stale
+    });
+    drop(live);
+    // Dropped outside the borrow, in case a state's `Drop` runs a job of another `StatePool`.
+    drop(stale);
+}
+
+/// Runs `f` with this thread's state for `init`, building it first if needed.
+///
+/// The state is taken out of the thread-local while `f` runs, so a job that runs another job
+/// of the same pool on this thread, for example while waiting for a scope, gets a fresh state
+/// for it instead of a second borrow. The outer job's state then replaces the inner one. If `f`
+/// panics the state is dropped, since it may have been left half updated, and the next job
+/// builds a new one.
+fn with_state<S: 'static, T>(init: &Init<S>, f: impl FnOnce(&mut S) -> T) -> T {
+    let dropped = DROPPED.load(Ordering::SeqCst);
+    if SEEN_DROPPED.replace(dropped) != dropped {
+        drop_stale_states();
+    }
+    let taken = STATES.with_borrow_mut(|states| {
+        let index = states.iter().position(|(id, _)| *id == init.id)?;
+        Some(states.swap_remove(index).1)
+    });
+    let mut state = match taken.map(|state| state.downcast::<S>()) {
+        Some(Ok(state)) => state,
+        _ => Box::new((init.init)()),
+    };
+
+    let result = f(&mut state);
+
+    let replaced = STATES.with_borrow_mut(|states| {
+        match states.iter_mut().find(|(id, _)| *id == init.id) {
+            Some((_, inner)) => Some(mem::replace(inner, state as Box<dyn Any>)),
+            None => {
+                states.push((init.id, state));
+                None
+            }
+        }
+    });
+    drop(replaced);
+    // The pool may have been dropped while `f` ran.
+    if DROPPED.load(Ordering::SeqCst) != dropped {
+        drop_stale_states();
+    }
+    result
+}
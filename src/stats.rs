@@ -0,0 +1,214 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::affinity::Affinity;
+use crate::priority::QueueLengths;
+use crate::worker::WorkerCounters;
+
+/// Number of histogram buckets. Bucket `i` holds durations below `2^i` microseconds,
+/// the last one everything longer.
+const BUCKETS: usize = 32;
+
+/// Point in time view of a pool returned by `ThreadPool::stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    /// Affinity the pool was configured with; see `WorkerStats::cpus` for the effective one.
+    pub affinity: Affinity,
+    /// `true` while the pool is paused with `ThreadPool::pause`.
+    pub paused: bool,
+    /// Jobs waiting in the queue at each priority.
+    pub queued: QueueLengths,
+    /// Worker threads currently alive.
+    pub workers: usize,
+    /// Workers running a job right now.
+    pub active_workers: usize,
+    /// Jobs accepted into the queue since the pool was created.
+    pub submitted: u64,
+    /// Jobs that ran to completion.
+    pub completed: u64,
+    /// Jobs that panicked.
+    pub panicked: u64,
+    /// Queued jobs discarded because of `ShutdownPolicy::Abandon` or `RejectionPolicy::DiscardOldest`.
+    pub discarded: u64,
+    /// Jobs that ran longer than the soft time limit.
+    pub slow_jobs: u64,
+    /// Workers replaced by the watchdog because their job exceeded the hard time limit.
+    pub lost_workers: u64,
+    /// Time jobs spent in the queue before a worker picked them up.
+    pub queue_wait: Histogram,
+    /// Time jobs spent running.
+    pub execution: Histogram,
+    pub per_worker: Vec<WorkerStats>,
+    /// Workers that are gone from `per_worker` because they retired.
+    pub retired: RetiredWorkers,
+    /// Workers that are gone from `per_worker` because the watchdog replaced them. Their
+    /// threads keep counting the job they were stuck in until it returns.
+    pub lost: RetiredWorkers,
+}
+
+/// Counters of a single worker in `PoolStats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub id: usize,
+    /// `true` while the worker is running a job.
+    pub active: bool,
+    /// How long the worker has been running its current job.
+    pub running_for: Option<Duration>,
+    /// Cores the worker is pinned to, `None` if it is not pinned.
+    pub cpus: Option<Vec<usize>>,
+    pub completed: usize,
+    pub panicked: usize,
+    /// Total time spent running jobs, including jobs that panicked.
+    pub busy: Duration,
+}
+
+/// Summed counters of workers no longer listed per worker: workers an elastic pool retired
+/// after `keep_alive` without a job, or workers lost to the hard time limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RetiredWorkers {
+    /// Number of workers.
+    pub workers: usize,
+    pub completed: usize,
+    pub panicked: usize,
+    /// Total time the workers spent running jobs.
+    pub busy: Duration,
+}
+
+impl RetiredWorkers {
+    pub(crate) fn add(&mut self, counters: &WorkerCounters) {
+        self.workers += 1;
+        self.completed += counters.completed.load(Ordering::Relaxed);
+        self.panicked += counters.panicked.load(Ordering::Relaxed);
+        self.busy += Duration::from_nanos(counters.busy_nanos.load(Ordering::Relaxed));
+    }
+}
+
+/// Latency distribution with power of two microsecond buckets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    count: u64,
+    total: Duration,
+    max: Duration,
+    buckets: [u64; BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram { count: 0, total: Duration::ZERO, max: Duration::ZERO, buckets: [0; BUCKETS] }
+    }
+}
+
+impl Histogram {
+    /// Number of recorded samples.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Longest recorded sample.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Average of the recorded samples, zero if there are none.
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total.as_nanos() / count as u128) as u64),
+        }
+    }
+
+    /// Upper bound of the bucket holding the `quantile` (between 0 and 1) sample,
+    /// capped at `max`. Zero if there are no samples.
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+
+        let mut seen = 0;
+        for (bound, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                return bound.min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Upper bound and sample count of every bucket, shortest first. The last bucket has no
+    /// upper bound and reports `Duration::MAX`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets.iter().enumerate().map(|(i, count)| (bucket_bound(i), *count))
+    }
+}
+
+fn bucket_bound(index: usize) -> Duration {
+    if index + 1 == BUCKETS {
+        Duration::MAX
+    } else {
+        Duration::from_micros(1 << index)
+    }
+}
+
+fn bucket_index(sample: Duration) -> usize {
+    let micros = sample.as_micros().min(u64::MAX as u128) as u64;
+    // Smallest `i` with `micros < 2^i`.
+    ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+}
+
+/// Lock free histogram the workers record into.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder {
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Recorder {
+    pub(crate) fn record(&self, sample: Duration) {
+        let nanos = as_nanos(sample);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        // Most samples are not a new maximum; checking first saves the atomic update.
+        if nanos > self.max_nanos.load(Ordering::Relaxed) {
+            self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        }
+        self.buckets[bucket_index(sample)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Histogram {
+        let buckets = std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed));
+        Histogram {
+            // Summed from the buckets so the snapshot is consistent with itself.
+            count: buckets.iter().sum(),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+}
+
+/// Pool wide job counters. Unlike the per worker counters they survive retired workers.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) completed: AtomicU64,
+    pub(crate) panicked: AtomicU64,
+    pub(crate) discarded: AtomicU64,
+    pub(crate) slow_jobs: AtomicU64,
+    pub(crate) lost_workers: AtomicU64,
+    pub(crate) queue_wait: Recorder,
+    pub(crate) execution: Recorder,
+}
+
+pub(crate) fn as_nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u64::MAX as u128) as u64
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Instant;
+
+use crate::log::Level;
+use crate::{Shared, watchdog};
+use crate::worker::Worker;
+
+/// Messages delivered to the supervisor thread.
+pub(crate) enum Event {
+    /// A worker thread has ended, normally or by unwinding.
+    Exited(usize),
+    /// The pool is shutting down; the supervisor exits once every worker is gone.
+    Shutdown,
+}
+
+/// Starts the thread that joins exited workers and replaces the ones that died
+/// while the pool was still running, so the pool keeps its configured size.
+/// With time limits configured it also runs the watchdog.
+pub(crate) fn spawn(shared: Arc<Shared>, events: Receiver<Event>) -> Result<thread::JoinHandle<()>, std::io::Error> {
+    shared.thread_builder("supervisor").spawn(move || {
+        let check_interval = shared.time_limits.check_interval();
+        let mut next_check = check_interval.map(|interval| Instant::now() + interval);
+
+        loop {
+            let event = match next_check {
+                Some(next_check) => events.recv_timeout(next_check.saturating_duration_since(Instant::now())),
+                None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(Event::Exited(id)) => reap(&shared, id),
+                Ok(Event::Shutdown) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if let (Some(interval), Some(at)) = (check_interval, next_check) {
+                if Instant::now() >= at {
+                    watchdog::check(&shared);
+                    next_check = Some(Instant::now() + interval);
+                }
+            }
+
+            if shared.shutting_down.load(Ordering::SeqCst)
+                && shared.lock_workers().iter().all(|worker| worker.thread.is_none()) {
+                break;
+            }
+        }
+    })
+}
+
+fn reap(shared: &Arc<Shared>, id: usize) {
+    let mut workers = shared.lock_workers();
+    let Some(index) = workers.iter().position(|worker| worker.id == id) else {
+        return;
+    };
+    let worker = &mut workers[index];
+    let Some(thread) = worker.thread.take() else {
+        return;
+    };
+
+    if thread.join().is_err() {
+        worker.counters.panicked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if worker.retired {
+        let worker = workers.remove(index);
+        shared.lock_retired().add(&worker.counters);
+    } else if worker.counters.start_failed.load(Ordering::SeqCst) {
+        // Respawning would most likely fail the same way, over and over.
+        shared.log(Level::Error, Some(id), None, format_args!("Worker start hook failed; not respawning"));
+    } else if !shared.shutting_down.load(Ordering::SeqCst) {
+        shared.log(Level::Warn, Some(id), None, format_args!("Worker died; respawning"));
+
+        match Worker::spawn(id, Arc::clone(shared), Arc::clone(&worker.counters)) {
+            Ok(replacement) => *worker = replacement,
+            Err(err) => shared.log(Level::Error, Some(id), None, format_args!("Cannot respawn worker: {err}")),
+        }
+    }
+
+    shared.exited.notify_all();
+}
@@ -0,0 +1,227 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::PoolError;
+use crate::priority::Priority;
+use crate::Shared;
+
+/// Handle to a job scheduled with `execute_after`, `execute_at` or `execute_at_fixed_rate`.
+///
+/// Dropping the handle does not cancel the job.
+#[derive(Debug, Clone)]
+pub struct ScheduledHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduledHandle {
+    /// Prevents any run of the job that has not started yet.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the job was cancelled, dropped because the pool shut down, or, for a
+    /// fixed rate job, stopped by a panicking run.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+type Repeating = Arc<Mutex<dyn FnMut() + Send + 'static>>;
+
+/// Marks a fixed rate job cancelled if its run unwinds, since no further run is scheduled.
+struct CancelOnPanic<'a>(&'a AtomicBool);
+
+impl Drop for CancelOnPanic<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+enum Task {
+    Once(Box<dyn FnOnce() + Send + 'static>),
+    /// Re-armed `period` after its previous deadline once each run completes,
+    /// so runs never overlap.
+    FixedRate { f: Repeating, period: Duration },
+}
+
+struct Entry {
+    at: Instant,
+    /// Keeps entries with the same deadline in scheduling order.
+    seq: u64,
+    task: Task,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    /// Reversed so the `BinaryHeap` pops the earliest deadline first.
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    entries: BinaryHeap<Entry>,
+    next_seq: u64,
+    closed: bool,
+}
+
+/// Holds scheduled jobs until they are due and then hands them to the pool's queue.
+///
+/// The timer thread is only started when the first job is scheduled.
+#[derive(Default)]
+pub(crate) struct Timer {
+    state: Mutex<State>,
+    changed: Condvar,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl Timer {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn schedule_once(shared: &Arc<Shared>, at: Instant, f: Box<dyn FnOnce() + Send + 'static>) -> Result<ScheduledHandle, PoolError> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        Timer::insert(shared, at, Task::Once(f), Arc::clone(&cancelled))?;
+        Ok(ScheduledHandle { cancelled })
+    }
+
+    pub(crate) fn schedule_fixed_rate(shared: &Arc<Shared>, period: Duration, f: Repeating) -> Result<ScheduledHandle, PoolError> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        Timer::insert(shared, Instant::now() + period, Task::FixedRate { f, period }, Arc::clone(&cancelled))?;
+        Ok(ScheduledHandle { cancelled })
+    }
+
+    /// Adds an entry, or marks it cancelled and fails if the timer has been shut down.
+    fn insert(shared: &Arc<Shared>, at: Instant, task: Task, cancelled: Arc<AtomicBool>) -> Result<(), PoolError> {
+        let timer = &shared.timer;
+        timer.start(shared)?;
+
+        let mut state = timer.lock();
+        if state.closed {
+            cancelled.store(true, Ordering::SeqCst);
+            return Err(PoolError::ShutDown);
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.push(Entry { at, seq, task, cancelled });
+        timer.changed.notify_one();
+        Ok(())
+    }
+
+    fn start(&self, shared: &Arc<Shared>) -> Result<(), std::io::Error> {
+        let mut thread = self.thread.lock().unwrap_or_else(|err| err.into_inner());
+        if thread.is_none() && !self.lock().closed {
+            let shared = Arc::clone(shared);
+            let builder = shared.thread_builder("timer");
+            *thread = Some(builder.spawn(move || Timer::run(&shared))?);
+        }
+        Ok(())
+    }
+
+    fn run(shared: &Arc<Shared>) {
+        let timer = &shared.timer;
+        let mut state = timer.lock();
+        loop {
+            if state.closed {
+                break;
+            }
+
+            let now = Instant::now();
+            state = match state.entries.peek() {
+                None => timer.changed.wait(state).unwrap_or_else(|err| err.into_inner()),
+                Some(entry) if entry.at > now => {
+                    let timeout = entry.at - now;
+                    timer.changed.wait_timeout(state, timeout).unwrap_or_else(|err| err.into_inner()).0
+                }
+                Some(_) => {
+                    let entry = state.entries.pop().unwrap();
+                    drop(state);
+                    Timer::fire(shared, entry);
+                    timer.lock()
+                }
+            };
+        }
+    }
+
+    /// Queues a due entry on the pool. Blocks while the queue is full.
+    fn fire(shared: &Arc<Shared>, entry: Entry) {
+        let Entry { at, task, cancelled, .. } = entry;
+        if cancelled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let job: Box<dyn FnOnce() + Send + 'static> = match task {
+            Task::Once(f) => {
+                let cancelled = Arc::clone(&cancelled);
+                Box::new(move || {
+                    if !cancelled.load(Ordering::SeqCst) {
+                        f();
+                    }
+                })
+            }
+            Task::FixedRate { f, period } => {
+                let shared = Arc::clone(shared);
+                let cancelled = Arc::clone(&cancelled);
+                Box::new(move || {
+                    if cancelled.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    let guard = CancelOnPanic(&cancelled);
+                    (f.lock().unwrap_or_else(|err| err.into_inner()))();
+                    drop(guard);
+
+                    let next = Task::FixedRate { f, period };
+                    let _ = Timer::insert(&shared, at + period, next, cancelled);
+                })
+            }
+        };
+
+        let pushed = shared.queue.push(job, None, |job| shared.new_job(job, Priority::Normal));
+        if pushed.is_ok() {
+            shared.grow();
+        } else {
+            cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Stops the timer thread and cancels every pending job.
+    pub(crate) fn shutdown(&self) {
+        let entries = {
+            let mut state = self.lock();
+            state.closed = true;
+            std::mem::take(&mut state.entries)
+        };
+        self.changed.notify_all();
+
+        for entry in entries {
+            entry.cancelled.store(true, Ordering::SeqCst);
+        }
+
+        let thread = self.thread.lock().unwrap_or_else(|err| err.into_inner()).take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+}
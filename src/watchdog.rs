@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::log::Level;
+use crate::Shared;
+
+/// Longest time between two watchdog checks.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a job may run before the watchdog reports it or replaces its worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct TimeLimits {
+    pub(crate) soft: Option<Duration>,
+    pub(crate) hard: Option<Duration>,
+}
+
+impl TimeLimits {
+    /// How often the supervisor checks the running jobs, `None` without limits.
+    pub(crate) fn check_interval(&self) -> Option<Duration> {
+        let shortest = match (self.soft, self.hard) {
+            (Some(soft), Some(hard)) => soft.min(hard),
+            (limit, None) | (None, limit) => limit?,
+        };
+        Some((shortest / 4).min(MAX_CHECK_INTERVAL))
+    }
+}
+
+/// Reports jobs over the soft limit and replaces workers whose job is over the hard limit.
+///
+/// Called periodically by the supervisor thread.
+pub(crate) fn check(shared: &Arc<Shared>) {
+    let limits = shared.time_limits;
+    let now = shared.clock();
+    let mut workers = shared.lock_workers();
+
+    let mut lost = 0;
+    let mut index = 0;
+    while index < workers.len() {
+        let worker = &mut workers[index];
+        let Some((job_id, running_for)) = worker.running(now).filter(|_| worker.thread.is_some()) else {
+            index += 1;
+            continue;
+        };
+
+        if limits.soft.is_some_and(|soft| running_for >= soft) && worker.reported_job != Some(job_id) {
+            worker.reported_job = Some(job_id);
+            shared.metrics.slow_jobs.fetch_add(1, Ordering::Relaxed);
+            shared.log(Level::Warn, Some(worker.id), Some(job_id),
+                       format_args!("Job running for {running_for:?}, over the soft time limit"));
+        }
+
+        if limits.hard.is_some_and(|hard| running_for >= hard) && !shared.shutting_down.load(Ordering::SeqCst) {
+            worker.counters.lost.store(true, Ordering::SeqCst);
+            shared.metrics.lost_workers.fetch_add(1, Ordering::Relaxed);
+            shared.log(Level::Error, Some(worker.id), Some(job_id),
+                       format_args!("Job running for {running_for:?}, over the hard time limit; replacing worker"));
+            // Dropping the handle detaches the thread.
+            let worker = workers.remove(index);
+            shared.lock_lost().push(worker.counters);
+            lost += 1;
+        } else {
+            index += 1;
+        }
+    }
+
+    for _ in 0..lost {
+        if let Err(err) = shared.spawn_worker(&mut workers) {
+            shared.log(Level::Error, None, None, format_args!("Cannot replace lost worker: {err}"));
+        }
+    }
+    if lost > 0 {
+        shared.exited.notify_all();
+    }
+}
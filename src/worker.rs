@@ -0,0 +1,223 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, mpsc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::panic::{JobPanic, panic_message};
+use crate::priority::Priority;
+use crate::queue::{Local, Pop};
+use crate::shutdown::WorkerReport;
+use crate::stats::{as_nanos, WorkerStats};
+use crate::supervisor::Event;
+use crate::idle::Outstanding;
+use crate::log::Level;
+use crate::Shared;
+
+pub(crate) type Task = Box<dyn FnOnce() + Send + 'static>;
+
+/// A task together with the id and priority it was given on submission.
+pub(crate) struct Job {
+    pub(crate) id: u64,
+    pub(crate) priority: Priority,
+    pub(crate) queued_at: Instant,
+    pub(crate) task: Task,
+    /// Released once the job has run or been discarded.
+    pub(crate) outstanding: Outstanding,
+}
+
+#[derive(Debug)]
+pub(crate) struct Worker {
+    pub(crate) id: usize,
+    pub(crate) thread: Option<thread::JoinHandle<()>>,
+    pub(crate) counters: Arc<WorkerCounters>,
+    /// Set when an idle worker of an elastic pool exits; it is removed instead of respawned.
+    pub(crate) retired: bool,
+    /// Job the watchdog last reported for exceeding the soft time limit.
+    pub(crate) reported_job: Option<u64>,
+    /// Receives the panic message of the start hook, or `Ok` once it has returned. Only
+    /// `build` waits for it.
+    pub(crate) started: Option<mpsc::Receiver<Result<(), String>>>,
+}
+
+/// Per worker job counters, shared between the worker thread and the pool.
+///
+/// The counters outlive a single thread: a respawned worker keeps adding to them. Only the
+/// thread currently running changes the job counts and `busy_nanos`, so it updates them with
+/// plain loads and stores instead of atomic increments.
+#[derive(Debug, Default)]
+pub(crate) struct WorkerCounters {
+    pub(crate) completed: AtomicUsize,
+    pub(crate) abandoned: AtomicUsize,
+    pub(crate) panicked: AtomicUsize,
+    pub(crate) busy_nanos: AtomicU64,
+    /// When the current job started, as `Shared::clock` plus one; 0 while idle.
+    pub(crate) job_started: AtomicU64,
+    pub(crate) current_job: AtomicU64,
+    /// Set by the watchdog when it replaced the worker; the thread exits after its current job.
+    pub(crate) lost: AtomicBool,
+    /// Set when the start hook panicked; the thread exits and is not respawned.
+    pub(crate) start_failed: AtomicBool,
+    /// Cores the thread is pinned to, set when it starts.
+    pub(crate) cpus: Mutex<Option<Vec<usize>>>,
+}
+
+/// Adds one to a counter of `WorkerCounters` from the worker thread.
+fn increment(counter: &AtomicUsize) {
+    counter.store(counter.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+}
+
+/// Hands the worker's local jobs back to the queue and notifies the supervisor that the
+/// worker thread has ended, even if it is unwinding.
+struct ExitGuard {
+    id: usize,
+    shared: Arc<Shared>,
+    local: Arc<Local>,
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        self.shared.queue.unregister(&self.local);
+        if let Some(on_thread_stop) = &self.shared.on_thread_stop {
+            // Panicking again while unwinding would abort the process.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| on_thread_stop(self.id)));
+        }
+        let _ = self.shared.events.send(Event::Exited(self.id));
+    }
+}
+
+impl Worker {
+    pub(crate) fn new(id: usize, shared: Arc<Shared>) -> Result<Worker, std::io::Error> {
+        Worker::spawn(id, shared, Arc::new(WorkerCounters::default()))
+    }
+
+    /// Starts a worker thread that adds to existing `counters`.
+    pub(crate) fn spawn(id: usize, shared: Arc<Shared>, counters: Arc<WorkerCounters>) -> Result<Worker, std::io::Error> {
+        let mut builder = shared.thread_builder(id);
+        if let Some(stack_size) = shared.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let thread_counters = Arc::clone(&counters);
+        let (started_sender, started) = mpsc::channel();
+
+        let thread = builder.spawn(move || {
+            let local = shared.queue.register();
+            let guard = ExitGuard { id, shared, local };
+            let shared = &guard.shared;
+            *thread_counters.cpus.lock().unwrap_or_else(|err| err.into_inner()) = shared.pin_worker(id);
+            if let Some(on_thread_start) = &shared.on_thread_start {
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| on_thread_start(id))) {
+                    let message = panic_message(payload.as_ref());
+                    shared.log(Level::Error, Some(id), None, format_args!("Start hook panicked: {message}"));
+                    thread_counters.start_failed.store(true, Ordering::SeqCst);
+                    let _ = started_sender.send(Err(message));
+                    return;
+                }
+            }
+            let _ = started_sender.send(Ok(()));
+
+            // When the previous job ended, if the worker has not waited for a job since.
+            let mut last_ended = None;
+            loop {
+                match shared.queue.pop(&guard.local, shared.keep_alive) {
+                    Pop::Job(job, _) if shared.abandon.load(Ordering::SeqCst) => {
+                        increment(&thread_counters.abandoned);
+                        shared.metrics.discarded.fetch_add(1, Ordering::Relaxed);
+                        drop(job);
+                    }
+                    Pop::Job(Job { id: job_id, queued_at, task, outstanding, .. }, waited) => {
+                        shared.log(Level::Trace, Some(id), Some(job_id), format_args!("Executing job"));
+
+                        // A job found right away starts when the previous one ended, give or
+                        // take the time to find it, which saves reading the clock.
+                        let started = match last_ended {
+                            Some(ended) if !waited => ended,
+                            _ => Instant::now(),
+                        };
+                        shared.metrics.queue_wait.record(started.saturating_duration_since(queued_at));
+                        thread_counters.current_job.store(job_id, Ordering::Relaxed);
+                        thread_counters.job_started.store(shared.clock_at(started) + 1, Ordering::Relaxed);
+
+                        let result = panic::catch_unwind(AssertUnwindSafe(task));
+
+                        let ended = Instant::now();
+                        let elapsed = ended.saturating_duration_since(started);
+                        last_ended = Some(ended);
+                        thread_counters.job_started.store(0, Ordering::Relaxed);
+                        let busy = thread_counters.busy_nanos.load(Ordering::Relaxed);
+                        thread_counters.busy_nanos.store(busy + as_nanos(elapsed), Ordering::Relaxed);
+                        shared.metrics.execution.record(elapsed);
+
+                        match result {
+                            Ok(()) => {
+                                increment(&thread_counters.completed);
+                                shared.metrics.completed.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(payload) => {
+                                increment(&thread_counters.panicked);
+                                shared.metrics.panicked.fetch_add(1, Ordering::Relaxed);
+                                shared.record_panic(JobPanic {
+                                    job_id,
+                                    worker_id: id,
+                                    message: panic_message(payload.as_ref()),
+                                });
+                            }
+                        }
+                        drop(outstanding);
+
+                        if thread_counters.lost.load(Ordering::SeqCst) {
+                            shared.log(Level::Warn, Some(id), Some(job_id), format_args!("Lost worker finished its job; exiting"));
+                            break;
+                        }
+                    }
+                    Pop::TimedOut => {
+                        if shared.retire(id) {
+                            shared.log(Level::Debug, Some(id), None, format_args!("Worker idle; retiring"));
+                            break;
+                        }
+                    }
+                    Pop::Closed => {
+                        shared.log(Level::Debug, Some(id), None, format_args!("Worker disconnected; shutting down"));
+                        break;
+                    }
+                }
+            }
+        })?;
+
+        Ok(Worker { id, thread: Some(thread), counters, retired: false, reported_job: None, started: Some(started) })
+    }
+
+    /// Returns the current job and how long it has been running, as of `now` on `Shared::clock`.
+    pub(crate) fn running(&self, now: u64) -> Option<(u64, Duration)> {
+        match self.counters.job_started.load(Ordering::Relaxed) {
+            0 => None,
+            started => {
+                let job_id = self.counters.current_job.load(Ordering::Relaxed);
+                Some((job_id, Duration::from_nanos(now.saturating_sub(started - 1))))
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self, now: u64) -> WorkerStats {
+        let running = self.running(now);
+        WorkerStats {
+            id: self.id,
+            active: running.is_some(),
+            running_for: running.map(|(_, running_for)| running_for),
+            cpus: self.counters.cpus.lock().unwrap_or_else(|err| err.into_inner()).clone(),
+            completed: self.counters.completed.load(Ordering::Relaxed),
+            panicked: self.counters.panicked.load(Ordering::Relaxed),
+            busy: Duration::from_nanos(self.counters.busy_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub(crate) fn report(&self) -> WorkerReport {
+        WorkerReport {
+            id: self.id,
+            completed: self.counters.completed.load(Ordering::Relaxed),
+            abandoned: self.counters.abandoned.load(Ordering::Relaxed),
+            panicked: self.counters.panicked.load(Ordering::Relaxed),
+            finished: self.thread.is_none(),
+        }
+    }
+}